@@ -0,0 +1,99 @@
+use crate::{CamId, Car, SpeedCam, TrafficItem, TrafficList};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Anticipation {
+    // Probability to notice a camera once it is in sight
+    pub awareness: f64,
+    // Hardest deceleration used to reach the limit at the start of the zone
+    pub braking: f64,
+    // Gain on the acceleration of a driver speeding up again after the zone, over the recovery distance
+    pub kangaroo: f64,
+    pub recovery: f64,
+}
+
+impl Default for Anticipation {
+    fn default() -> Self {
+        Self {
+            awareness: 1.0,
+            braking: 1.5,
+            kangaroo: 1.5,
+            recovery: 100.0,
+        }
+    }
+}
+
+impl Anticipation {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0f64..=1f64).contains(&self.awareness) {
+            return Err(format!("awareness {} should lie in [0, 1]", self.awareness));
+        }
+        for (name, value) in [("braking", self.braking), ("kangaroo", self.kangaroo), ("recovery", self.recovery)] {
+            if value.is_nan() || value < 0f64 {
+                return Err(format!("{} {} should be non-negative", name, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn acceleration(&self, car: &Car, acc: f64, view: &View, cams: &[SpeedCam]) -> f64 {
+        // Acceleration of `car` once it takes the cameras it noticed into account
+        let noticed = |id: CamId| matches!(car.noticed, Some((seen, true)) if seen == id);
+        if let Some((id, distance)) = view.ahead {
+            let cam = &cams[id.0];
+            if distance < cam.sight_distance && noticed(id) {
+                let (v, target) = (car.vel[0], (1f64 + car.behavior) * cam.limit_for(car.class));
+                if v > target {
+                    let needed = (v * v - target * target) / (2f64 * distance.max(1e-3));
+                    return acc.min(-needed.min(self.braking));
+                }
+            }
+        }
+        if let Some((id, distance)) = view.behind {
+            if distance < self.recovery && noticed(id) && acc > 0f64 {
+                return acc * self.kangaroo;
+            }
+        }
+        acc
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct View {
+    // Next camera zone ahead with the distance to its start, and the last one behind with the distance from its end
+    pub ahead: Option<(CamId, f64)>,
+    pub behind: Option<(CamId, f64)>,
+}
+
+pub fn views(traffic: &TrafficList, length: f64, periodic: bool) -> Vec<View> {
+    // One view per item of the list, cameras past the seam of a ring being seen around it
+    let items: Vec<&TrafficItem> = traffic.iter().collect();
+    let flags = || {
+        items.iter().filter_map(|item| match item {
+            TrafficItem::Flag(f) => Some(f),
+            _ => None,
+        })
+    };
+    let first_open = flags().find(|f| f.status).filter(|_| periodic);
+    let last_close = flags().rfind(|f| !f.status).filter(|_| periodic);
+
+    let mut views = vec![View::default(); items.len()];
+    let mut ahead = first_open.map(|f| (f.cam, f.pos[0] + length));
+    for (i, item) in items.iter().enumerate().rev() {
+        match item {
+            TrafficItem::Flag(f) if f.status => ahead = Some((f.cam, f.pos[0])),
+            TrafficItem::Flag(_) | TrafficItem::Fixture(_) => {}
+            TrafficItem::Car(c) => views[i].ahead = ahead.map(|(cam, x)| (cam, x - c.pos[0])),
+        }
+    }
+    let mut behind = last_close.map(|f| (f.cam, f.pos[0] - length));
+    for (i, item) in items.iter().enumerate() {
+        match item {
+            TrafficItem::Flag(f) if !f.status => behind = Some((f.cam, f.pos[0])),
+            TrafficItem::Flag(_) | TrafficItem::Fixture(_) => {}
+            TrafficItem::Car(c) => views[i].behind = behind.map(|(cam, x)| (cam, c.pos[0] - x)),
+        }
+    }
+    views
+}
@@ -0,0 +1,364 @@
+use crate::recorder::{Recorder, Sample};
+use crate::scenario::CameraSpec;
+use crate::{CamId, CarId, Violation, ViolationLog};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CellularSpec {
+    pub cell_length: f64,
+    pub dt: f64,
+    // Probability of braking by one cell per step
+    pub randomization: f64,
+    // Probability for a car at rest to stay at rest (Benjamin, Johnson and Hui)
+    pub slow_to_start: Option<f64>,
+    // Randomization of cars at rest, replacing the usual one (velocity dependent randomization)
+    pub vdr: Option<f64>,
+    pub seed: u64,
+}
+
+impl Default for CellularSpec {
+    fn default() -> Self {
+        Self {
+            cell_length: 7.5,
+            dt: 1.0,
+            randomization: 0.25,
+            slow_to_start: None,
+            vdr: None,
+            seed: 0,
+        }
+    }
+}
+
+impl CellularSpec {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.cell_length > 0f64 && self.dt > 0f64) {
+            return Err(format!("cell length {} and time step {} should be positive", self.cell_length, self.dt));
+        }
+        let probabilities = [Some(self.randomization), self.slow_to_start, self.vdr];
+        if let Some(p) = probabilities.iter().flatten().find(|p| !(0f64..=1f64).contains(*p)) {
+            return Err(format!("probability {} should lie in [0, 1]", p));
+        }
+        Ok(())
+    }
+
+    pub fn cells(&self, speed: f64) -> usize {
+        // Speed in cells per step, rounded down
+        (speed * self.dt / self.cell_length).floor().max(0f64) as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CellZone {
+    // Cells [start, end) with the camera at the cell `end`
+    pub start: usize,
+    pub end: usize,
+    pub speed_limit: f64,
+    pub tolerance: f64,
+    pub check_average: bool,
+}
+
+impl CellZone {
+    pub fn from_cam(cam: &CameraSpec, cell_length: f64) -> Result<Self, String> {
+        // A zone starting before the road would be cut short at its first cell
+        let start = cam.pos - cam.length;
+        if start.is_nan() || start < 0f64 {
+            return Err(format!("zone [{}, {}] starts before the road", start, cam.pos));
+        }
+        Ok(Self {
+            start: (start / cell_length).floor() as usize,
+            end: (cam.pos / cell_length).floor() as usize,
+            speed_limit: cam.speed_limit,
+            tolerance: cam.tolerance,
+            check_average: cam.check_average,
+        })
+    }
+
+    pub fn contains(&self, cell: usize) -> bool {
+        (self.start..self.end).contains(&cell)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vehicle {
+    pub id: CarId,
+    pub lane: usize,
+    pub cell: usize,
+    pub vel: usize,
+    pub v_max: usize,
+    pub behavior: f64,
+    // Step at which the car entered each section control it is in, by index of the zone
+    #[serde(default)]
+    pub entered: BTreeMap<CamId, usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CellularRoad {
+    spec: CellularSpec,
+    cells: usize,
+    lanes: usize,
+    vehicles: Vec<Vehicle>,
+    zones: Vec<CellZone>,
+    rng: Pcg64,
+    steps: usize,
+    log: ViolationLog,
+}
+
+impl CellularRoad {
+    pub fn new(cells: usize, lanes: usize, spec: CellularSpec) -> Self {
+        if cells == 0 || lanes == 0 {
+            panic!("Invalid road : {} cells and {} lanes should both be positive", cells, lanes);
+        }
+        if let Err(message) = spec.validate() {
+            panic!("Invalid cellular automaton : {}", message);
+        }
+        Self {
+            rng: Pcg64::seed_from_u64(spec.seed),
+            spec,
+            cells,
+            lanes,
+            vehicles: vec![],
+            zones: vec![],
+            steps: 0,
+            log: ViolationLog::new(),
+        }
+    }
+
+    pub fn with_zone(mut self, zone: CellZone) -> Self {
+        if zone.start >= zone.end || zone.end >= self.cells {
+            panic!("Invalid zone : cells [{}, {}) do not fit on a road of {} cells", zone.start, zone.end, self.cells);
+        }
+        self.zones.push(zone);
+        self
+    }
+
+    pub fn insert(&mut self, lane: usize, cell: usize, vel: usize, v_max: usize, behavior: f64) -> Option<CarId> {
+        // None if the cell is taken or outside of the road
+        if lane >= self.lanes || cell >= self.cells || self.vehicles.iter().any(|v| v.lane == lane && v.cell == cell) {
+            return None;
+        }
+        let id = CarId(self.vehicles.iter().map(|v| v.id.0 + 1).max().unwrap_or(0));
+        self.vehicles.push(Vehicle { id, lane, cell, vel: vel.min(v_max), v_max, behavior, entered: BTreeMap::new() });
+        self.vehicles.sort_by_key(|v| (v.lane, v.cell));
+        Some(id)
+    }
+
+    pub fn spec(&self) -> &CellularSpec {
+        &self.spec
+    }
+
+    pub fn cells(&self) -> usize {
+        self.cells
+    }
+
+    pub fn time(&self) -> f64 {
+        self.steps as f64 * self.spec.dt
+    }
+
+    pub fn vehicles(&self) -> &[Vehicle] {
+        &self.vehicles
+    }
+
+    pub fn violations(&self) -> &ViolationLog {
+        &self.log
+    }
+
+    pub fn density(&self) -> f64 {
+        // Cars per cell of a single lane
+        self.vehicles.len() as f64 / (self.cells * self.lanes) as f64
+    }
+
+    pub fn flow(&self) -> f64 {
+        // Cars passing a cell boundary per step on a single lane
+        self.vehicles.iter().map(|v| v.vel).sum::<usize>() as f64 / (self.cells * self.lanes) as f64
+    }
+
+    fn v_max(&self, vehicle: &Vehicle) -> usize {
+        // Inside a zone drivers go at their own tolerance of the limit, as `Car::set_max_speed`,
+        // and heed the stricter limit where zones overlap
+        self.zones
+            .iter()
+            .filter(|z| z.contains(vehicle.cell))
+            .map(|zone| self.spec.cells((1f64 + vehicle.behavior) * zone.speed_limit).max(1))
+            .fold(vehicle.v_max, usize::min)
+    }
+
+    fn crossed(&self, from: usize, vel: usize, cell: usize) -> bool {
+        // The car moved over `cell` this step, going around the ring if needed
+        (1..=vel).contains(&((cell + self.cells - from) % self.cells))
+    }
+
+    pub fn step(&mut self) {
+        // Parallel update of every lane : acceleration, braking, randomization and movement
+        let n = self.vehicles.len();
+        let mut vels = Vec::with_capacity(n);
+        for k in 0..n {
+            let vehicle = &self.vehicles[k];
+            let next = if k + 1 < n && self.vehicles[k + 1].lane == vehicle.lane {
+                Some(&self.vehicles[k + 1])
+            } else {
+                self.vehicles[..=k].iter().find(|v| v.lane == vehicle.lane).filter(|v| v.id != vehicle.id)
+            };
+            let gap = match next {
+                Some(leader) => (leader.cell + self.cells - vehicle.cell - 1) % self.cells,
+                None => self.cells - 1,
+            };
+
+            let at_rest = vehicle.vel == 0;
+            let mut vel = vehicle.vel;
+            let stays = at_rest && matches!(self.spec.slow_to_start, Some(q) if self.rng.gen::<f64>() < q);
+            if !stays {
+                vel = (vel + 1).min(self.v_max(vehicle));
+            }
+            vel = vel.min(gap);
+            let p = match self.spec.vdr {
+                Some(p0) if at_rest => p0,
+                _ => self.spec.randomization,
+            };
+            if vel > 0 && self.rng.gen::<f64>() < p {
+                vel -= 1;
+            }
+            vels.push(vel);
+        }
+
+        self.steps += 1;
+        let time = self.time();
+        let cell_length = self.spec.cell_length;
+        let dt = self.spec.dt;
+        for (k, &vel) in vels.iter().enumerate() {
+            let from = self.vehicles[k].cell;
+            for (i, zone) in self.zones.iter().enumerate() {
+                // Every zone keeps its own clock, so overlapping or successive sections do not cut each other short
+                if zone.check_average && self.crossed(from, vel, zone.start) {
+                    self.vehicles[k].entered.insert(CamId(i), self.steps);
+                }
+                if self.crossed(from, vel, zone.end) {
+                    let speed = if zone.check_average {
+                        match self.vehicles[k].entered.remove(&CamId(i)) {
+                            Some(step) => (zone.end - zone.start) as f64 * cell_length / ((self.steps - step) as f64 * dt).max(dt),
+                            None => continue,
+                        }
+                    } else {
+                        vel as f64 * cell_length / dt
+                    };
+                    if speed > zone.speed_limit * zone.tolerance {
+                        self.log.push(Violation {
+                            car: self.vehicles[k].id,
+                            time,
+                            pos: zone.end as f64 * cell_length,
+                            measured_speed: speed,
+                            speed_limit: zone.speed_limit,
+                            tolerance: zone.tolerance,
+                            average: zone.check_average,
+                        });
+                    }
+                }
+            }
+            let vehicle = &mut self.vehicles[k];
+            vehicle.vel = vel;
+            vehicle.cell = (from + vel) % self.cells;
+        }
+        self.vehicles.sort_by_key(|v| (v.lane, v.cell));
+    }
+
+    pub fn run_until(&mut self, tmax: f64) {
+        while self.time() + 0.5 * self.spec.dt < tmax {
+            self.step();
+        }
+    }
+
+    pub fn samples(&self) -> impl Iterator<Item = Sample> + '_ {
+        let (cell_length, dt, time) = (self.spec.cell_length, self.spec.dt, self.time());
+        self.vehicles.iter().map(move |v| Sample {
+            time,
+            car: v.id,
+            pos: v.cell as f64 * cell_length,
+            vel: v.vel as f64 * cell_length / dt,
+            lane: v.lane,
+            max_speed: self.v_max(v) as f64 * cell_length / dt,
+        })
+    }
+
+    pub fn run_recorded(&mut self, tmax: f64, recorder: &mut Recorder) {
+        recorder.record(self.time(), self.spec.dt, self.samples());
+        while self.time() + 0.5 * self.spec.dt < tmax {
+            self.step();
+            recorder.record(self.time(), self.spec.dt, self.samples());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    fn ring(cars: usize, cells: usize, spec: CellularSpec) -> CellularRoad {
+        let mut road = CellularRoad::new(cells, 1, spec);
+        for k in 0..cars {
+            road.insert(0, k * cells / cars, 0, 5, 0f64).unwrap();
+        }
+        road
+    }
+
+    #[test]
+    fn test_nasch_deterministic() {
+        // Without randomization the flow is min(rho * v_max, 1 - rho)
+        let spec = CellularSpec { randomization: 0f64, ..Default::default() };
+        for (cars, flow) in [(10, 0.5), (50, 0.5), (80, 0.2)] {
+            let mut road = ring(cars, 100, spec.clone());
+            road.run_until(500f64);
+            assert_abs_diff_eq!(road.flow(), flow, epsilon = 1e-12);
+        }
+        assert!(ring(2, 100, spec).insert(0, 0, 0, 5, 0f64).is_none());
+    }
+
+    #[test]
+    fn test_nasch_seed() {
+        let spec = CellularSpec { slow_to_start: Some(0.5), vdr: Some(0.6), seed: 7, ..Default::default() };
+        let mut a = ring(30, 100, spec.clone());
+        let mut b = ring(30, 100, spec);
+        a.run_until(200f64);
+        b.run_until(200f64);
+        assert_eq!(a.vehicles(), b.vehicles());
+        // Randomization keeps the flow below the deterministic one
+        assert!(a.flow() < 0.7);
+    }
+
+    #[test]
+    fn test_nasch_zone() {
+        let spec = CellularSpec { cell_length: 1f64, randomization: 0f64, ..Default::default() };
+        let zone = CellZone { start: 50, end: 80, speed_limit: 2f64, tolerance: 1.1, check_average: false };
+        let mut road = CellularRoad::new(100, 1, spec).with_zone(zone);
+        let compliant = road.insert(0, 0, 0, 5, 0f64).unwrap();
+        let speeding = road.insert(0, 40, 0, 5, 1f64).unwrap();
+        road.run_until(100f64);
+
+        let log = road.violations();
+        assert_eq!(log.of_car(compliant).count(), 0);
+        assert!(log.of_car(speeding).count() > 0);
+        // Once it catches up with the compliant car it is caught at a lower speed
+        assert_eq!(log.iter().next().unwrap().measured_speed, 4f64);
+    }
+
+    #[test]
+    fn test_nasch_sections() {
+        // Overlapping sections each time the car from their own start
+        let spec = CellularSpec { cell_length: 1f64, randomization: 0f64, ..Default::default() };
+        let outer = CellZone { start: 20, end: 50, speed_limit: 2f64, tolerance: 1.1, check_average: true };
+        let inner = CellZone { start: 40, end: 80, ..outer.clone() };
+        let mut road = CellularRoad::new(100, 1, spec).with_zone(outer).with_zone(inner);
+        road.insert(0, 0, 3, 5, 0.5).unwrap();
+        road.run_until(30f64);
+        assert_eq!(road.violations().len(), 2);
+        assert!(road.violations().iter().all(|v| v.average && (v.measured_speed - 3f64).abs() < 0.5));
+
+        // A zone reaching back past the start of the road is refused rather than cut short
+        let cam = CameraSpec { pos: 10f64, speed_limit: 2f64, length: 20f64, check_average: true, tolerance: 1.1, class_limits: vec![], sight_distance: 0f64 };
+        assert!(CellZone::from_cam(&cam, 1f64).is_err());
+        assert_eq!(CellZone::from_cam(&CameraSpec { length: 10f64, ..cam }, 1f64).unwrap().start, 0);
+    }
+}
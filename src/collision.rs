@@ -0,0 +1,54 @@
+use crate::CarId;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionPolicy {
+    // Crashes are only recorded, the cars drive on through each other
+    #[default]
+    Record,
+    // Both cars are taken off the road
+    Remove,
+    // Both cars stop where they are and stay there as obstacles
+    Block,
+    // The run stops at the first crash
+    Abort,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Crash {
+    pub time: f64,
+    pub lane: usize,
+    pub pos: f64,
+    pub rear: CarId,
+    pub front: CarId,
+    pub rear_speed: f64,
+    pub front_speed: f64,
+    // Length over which the cars overlap, beyond their size when the rear car went through the front one
+    pub overlap: f64,
+}
+
+impl Crash {
+    pub fn is_near(&self, pos: f64, radius: f64) -> bool {
+        (self.pos - pos).abs() <= radius
+    }
+}
+
+impl std::fmt::Display for Crash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "car {} ran into car {} on lane {} at {} and time {} ({} against {})",
+            self.rear, self.front, self.lane, self.pos, self.time, self.rear_speed, self.front_speed
+        )
+    }
+}
+
+pub fn write_csv<W: Write>(crashes: &[Crash], mut writer: W) -> std::io::Result<()> {
+    writeln!(writer, "time,lane,pos,rear,front,rear_speed,front_speed,overlap")?;
+    for c in crashes {
+        writeln!(writer, "{},{},{},{},{},{},{},{}", c.time, c.lane, c.pos, c.rear, c.front, c.rear_speed, c.front_speed, c.overlap)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,211 @@
+use crate::measure::{Segment, SegmentMonitor};
+use crate::scenario::{positive, CarParams, ScenarioError};
+use crate::{Road, Simulation};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+
+fn one() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sweep {
+    pub length: f64,
+    #[serde(default = "one")]
+    pub lanes: usize,
+    pub dt: f64,
+    // Densities are in cars per unit length of a single lane
+    pub min_density: f64,
+    pub max_density: f64,
+    pub steps: usize,
+    pub equilibration: f64,
+    pub measurement: f64,
+    pub window: f64,
+    #[serde(default)]
+    pub seed: u64,
+    pub car: CarParams,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiagramPoint {
+    pub density: f64,
+    pub cars: usize,
+    pub flow: f64,
+    pub flow_err: f64,
+    pub speed: f64,
+    pub speed_err: f64,
+}
+
+fn mean_and_error(xs: &[f64]) -> (f64, f64) {
+    // Standard error of the mean over independent time windows
+    let n = xs.len() as f64;
+    let mean = xs.iter().sum::<f64>() / n;
+    if xs.len() < 2 {
+        return (mean, 0f64);
+    }
+    let var = xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1f64);
+    (mean, (var / n).sqrt())
+}
+
+impl Sweep {
+    pub fn from_toml(s: &str) -> Result<Self, ScenarioError> {
+        let sweep: Self = serde_path_to_error::deserialize(&mut toml::Deserializer::new(s))
+            .map_err(ScenarioError::parse)?;
+        sweep.validate()?;
+        Ok(sweep)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScenarioError> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let invalid = |field: &str, message: String| Err(ScenarioError::invalid(field.to_string(), message));
+        if !positive(self.length) {
+            return invalid("length", format!("length {} should be positive", self.length));
+        }
+        if self.lanes == 0 {
+            return invalid("lanes", "road should have at least one lane".to_string());
+        }
+        if !positive(self.dt) {
+            return invalid("dt", format!("time step {} should be positive", self.dt));
+        }
+        self.car.validate("car", self.lanes)?;
+        // The car is copied onto every lane of the ring
+        if let Some(class) = self.car.class.filter(|class| (0..self.lanes).any(|lane| !class.allows_lane(lane))) {
+            return invalid("lanes", format!("class {:?} may not use every one of the {} lanes", class, self.lanes));
+        }
+        if !(0f64 < self.min_density && self.min_density <= self.max_density) {
+            return invalid("min_density", format!("density range [{}, {}] is empty", self.min_density, self.max_density));
+        }
+        if self.max_density * self.car.size > 1f64 {
+            return invalid("max_density", format!("density {} makes cars of size {} overlap", self.max_density, self.car.size));
+        }
+        if self.steps == 0 {
+            return invalid("steps", "sweep should have at least one density".to_string());
+        }
+        if !(positive(self.window) && self.window <= self.measurement) {
+            return invalid("window", format!("window {} should be positive and fit in the measurement time {}", self.window, self.measurement));
+        }
+        if self.equilibration.is_nan() || self.equilibration < 0f64 {
+            return invalid("equilibration", format!("equilibration time {} should be non-negative", self.equilibration));
+        }
+        Ok(())
+    }
+
+    pub fn densities(&self) -> Vec<f64> {
+        if self.steps == 1 {
+            return vec![self.min_density];
+        }
+        let step = (self.max_density - self.min_density) / (self.steps - 1) as f64;
+        (0..self.steps).map(|k| self.min_density + step * k as f64).collect()
+    }
+
+    pub fn simulation(&self, density: f64) -> Result<Simulation, ScenarioError> {
+        // Cars start at rest, evenly spaced on every lane of the ring
+        let per_lane = ((density * self.length).round() as usize).max(1);
+        let mut cars = vec![];
+        for k in 0..per_lane {
+            for lane in 0..self.lanes {
+                let mut params = self.car.clone();
+                params.lane = lane;
+                cars.push(params.build(self.length * k as f64 / per_lane as f64, 0f64));
+            }
+        }
+        let road = Road::from_cars(cars, vec![]).map_err(|message| ScenarioError::invalid("car".to_string(), message))?;
+        Ok(Simulation::new(road, self.length, self.dt).with_lanes(self.lanes).with_seed(self.seed))
+    }
+
+    pub fn point(&self, density: f64) -> Result<DiagramPoint, ScenarioError> {
+        let mut sim = self.simulation(density)?;
+        sim.run_until(self.equilibration);
+
+        let mut monitor = SegmentMonitor::new(Segment::new(0f64, self.length), self.window);
+        sim.run_observed(self.equilibration + self.measurement, &mut monitor);
+
+        let lanes = self.lanes as f64;
+        let flows: Vec<f64> = monitor.measurements().iter().map(|m| m.flow / lanes).collect();
+        let speeds: Vec<f64> = monitor.measurements().iter().filter_map(|m| m.space_mean_speed).collect();
+        let (flow, flow_err) = mean_and_error(&flows);
+        let (speed, speed_err) = mean_and_error(&speeds);
+
+        Ok(DiagramPoint {
+            density: sim.cars().count() as f64 / (self.length * lanes),
+            cars: sim.cars().count(),
+            flow,
+            flow_err,
+            speed,
+            speed_err,
+        })
+    }
+
+    pub fn run(&self) -> Result<Vec<DiagramPoint>, ScenarioError> {
+        self.densities().into_iter().map(|rho| self.point(rho)).collect()
+    }
+}
+
+pub fn write_csv<W: Write>(points: &[DiagramPoint], mut writer: W) -> std::io::Result<()> {
+    writeln!(writer, "density,cars,flow,flow_err,speed,speed_err")?;
+    for p in points {
+        writeln!(writer, "{},{},{},{},{},{}", p.density, p.cars, p.flow, p.flow_err, p.speed, p.speed_err)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    const SWEEP: &str = r#"
+        length = 500.0
+        dt = 0.1
+        min_density = 0.01
+        max_density = 0.03
+        steps = 3
+        equilibration = 100.0
+        measurement = 100.0
+        window = 25.0
+
+        [car]
+        size = 1.0
+        max_speed = 10.0
+        drift = 1.0
+        own_max_speed = 10.0
+    "#;
+
+    #[test]
+    fn test_sweep_free_flow() {
+        let sweep = Sweep::from_toml(SWEEP).unwrap();
+        for (rho, expected) in sweep.densities().into_iter().zip([0.01, 0.02, 0.03]) {
+            assert_abs_diff_eq!(rho, expected, epsilon = 1e-12);
+        }
+
+        let points = sweep.run().unwrap();
+        assert_eq!(points.len(), 3);
+        for p in points.iter() {
+            // Sparse traffic cruises at the desired speed, so q = rho * v
+            assert_abs_diff_eq!(p.speed, 10f64, epsilon = 0.1);
+            assert_abs_diff_eq!(p.flow, p.density * p.speed, epsilon = 1e-3);
+            assert!(p.flow_err < 1e-2);
+        }
+        assert_eq!(points[2].cars, 15);
+
+        let mut csv = vec![];
+        write_csv(&points, &mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 4);
+    }
+
+    #[test]
+    fn test_sweep_invalid() {
+        let err = Sweep::from_toml(&SWEEP.replace("max_density = 0.03", "max_density = 2.0")).unwrap_err();
+        assert!(matches!(err, ScenarioError::Invalid { field, .. } if field == "max_density"));
+
+        // Trucks keep to the two right lanes, a sweep over three would put them on the third
+        let trucks = SWEEP.replace("[car]", "lanes = 3\n\n        [car]\n        class = \"truck\"");
+        let err = Sweep::from_toml(&trucks).unwrap_err();
+        assert!(matches!(err, ScenarioError::Invalid { field, .. } if field == "lanes"));
+        assert!(Sweep::from_toml(&trucks.replace("lanes = 3", "lanes = 2")).is_ok());
+    }
+}
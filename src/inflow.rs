@@ -0,0 +1,187 @@
+use crate::{Car, CarId, FixtureId};
+use rand::Rng;
+use rand_distr::{Distribution, Exp, Normal};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Arrivals {
+    // Exponential headways of mean 1 / rate
+    Poisson { rate: f64 },
+    FixedHeadway { headway: f64 },
+}
+
+impl Arrivals {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Arrivals::Poisson { rate } if rate.is_nan() || rate <= 0f64 => Err(format!("rate {} should be positive", rate)),
+            Arrivals::FixedHeadway { headway } if headway.is_nan() || headway <= 0f64 => Err(format!("headway {} should be positive", headway)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn headway<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        match *self {
+            Arrivals::Poisson { rate } => Exp::new(rate).unwrap().sample(rng),
+            Arrivals::FixedHeadway { headway } => headway,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SpeedDistribution {
+    Fixed { speed: f64 },
+    Uniform { min: f64, max: f64 },
+    // Negative draws are cut to zero
+    Normal { mean: f64, std: f64 },
+}
+
+impl SpeedDistribution {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            SpeedDistribution::Fixed { speed } if speed.is_nan() || speed < 0f64 => Err(format!("speed {} should be non-negative", speed)),
+            SpeedDistribution::Uniform { min, max } if !(0f64 <= min && min <= max) => Err(format!("speed range [{}, {}] is invalid", min, max)),
+            SpeedDistribution::Normal { std, .. } if !std.is_finite() || std < 0f64 => Err(format!("standard deviation {} should be finite and non-negative", std)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        match *self {
+            SpeedDistribution::Fixed { speed } => speed,
+            SpeedDistribution::Uniform { min, max } => min + (max - min) * rng.gen::<f64>(),
+            SpeedDistribution::Normal { mean, std } => Normal::new(mean, std).unwrap().sample(rng).max(0f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Inflow {
+    arrivals: Arrivals,
+    speed: SpeedDistribution,
+    template: Car,
+    next_arrival: Option<f64>,
+    queue: usize,
+    ramp: Option<FixtureId>,
+}
+
+impl Inflow {
+    // Copies of `template` enter at the upstream end or at the on ramp, on its lane
+    pub fn new(arrivals: Arrivals, speed: SpeedDistribution, template: Car) -> Self {
+        if let Err(message) = arrivals.validate().and(speed.validate()) {
+            panic!("Invalid inflow : {}", message);
+        }
+        Self {
+            arrivals,
+            speed,
+            template,
+            next_arrival: None,
+            queue: 0,
+            ramp: None,
+        }
+    }
+
+    pub fn with_ramp(mut self, ramp: FixtureId) -> Self {
+        self.ramp = Some(ramp);
+        self
+    }
+
+    pub fn ramp(&self) -> Option<FixtureId> {
+        self.ramp
+    }
+
+    pub fn lane(&self) -> usize {
+        self.template.lane
+    }
+
+    pub fn queue(&self) -> usize {
+        // Cars that arrived but found no room to enter yet
+        self.queue
+    }
+
+    pub fn arrive<R: Rng + ?Sized>(&mut self, time: f64, rng: &mut R) {
+        // The first car arrives one headway after the start
+        let mut next = match self.next_arrival {
+            Some(next) => next,
+            None => self.arrivals.headway(rng),
+        };
+        while next <= time {
+            self.queue += 1;
+            next += self.arrivals.headway(rng);
+        }
+        self.next_arrival = Some(next);
+    }
+
+    pub fn next_car<R: Rng + ?Sized>(&self, time: f64, rng: &mut R) -> Option<Car> {
+        if self.queue == 0 {
+            return None;
+        }
+        let mut car = self.template.clone();
+        car.pos[0] = 0f64;
+        car.vel[0] = self.speed.sample(rng);
+        car.entry_time = Some(time);
+        Some(car)
+    }
+
+    pub fn entered(&mut self) {
+        self.queue -= 1;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exit {
+    pub car: CarId,
+    pub lane: usize,
+    // None for cars that were on the road from the start
+    pub entry_time: Option<f64>,
+    pub exit_time: f64,
+    pub speed: f64,
+    // Off ramp taken, None for cars leaving at the end of the road
+    #[serde(default)]
+    pub ramp: Option<FixtureId>,
+}
+
+impl Exit {
+    pub fn travel_time(&self) -> Option<f64> {
+        self.entry_time.map(|t| self.exit_time - t)
+    }
+}
+
+pub fn write_csv<W: Write>(exits: &[Exit], mut writer: W) -> std::io::Result<()> {
+    writeln!(writer, "car,lane,entry_time,exit_time,travel_time,speed,ramp")?;
+    for e in exits {
+        let field = |x: Option<f64>| x.map_or(String::new(), |x| x.to_string());
+        let ramp = e.ramp.map_or(String::new(), |r| r.0.to_string());
+        writeln!(writer, "{},{},{},{},{},{},{}", e.car, e.lane, field(e.entry_time), e.exit_time, field(e.travel_time()), e.speed, ramp)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use rand::SeedableRng;
+    use rand_pcg::Pcg64;
+
+    #[test]
+    fn test_arrivals() {
+        let mut rng = Pcg64::seed_from_u64(5);
+        let template = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        let mut poisson = Inflow::new(Arrivals::Poisson { rate: 0.5 }, SpeedDistribution::Fixed { speed: 5f64 }, template.clone());
+        poisson.arrive(10000f64, &mut rng);
+        assert_abs_diff_eq!(poisson.queue() as f64 / 10000f64, 0.5, epsilon = 0.02);
+
+        let uniform = SpeedDistribution::Uniform { min: 4f64, max: 6f64 };
+        let mut fixed = Inflow::new(Arrivals::FixedHeadway { headway: 2f64 }, uniform, template);
+        assert!(fixed.next_car(0f64, &mut rng).is_none());
+        fixed.arrive(9f64, &mut rng);
+        assert_eq!(fixed.queue(), 4);
+        let car = fixed.next_car(9f64, &mut rng).unwrap();
+        assert!((4f64..6f64).contains(&car.vel[0]));
+        fixed.entered();
+        assert_eq!(fixed.queue(), 3);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::signal::{Actuated, Signal};
+use crate::{Car, TrafficItem, TrafficList};
+use moldybrody::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// A car slower than this within STOP_RANGE of a stop sign has made its stop
+const STOP_SPEED: f64 = 0.5;
+const STOP_RANGE: f64 = 10.0;
+// Braking assumed to decide whether a car can still stop at a light, for cars without limits
+const STOP_DECELERATION: f64 = 3.0;
+// Cars on a lane that ends look for a gap on the lane next to it over this distance before the end
+pub const DROP_RANGE: f64 = 100.0;
+
+// Index of a fixture on its road
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct FixtureId(pub usize);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FixtureKind {
+    // Limit from here on, None lifting it
+    SpeedLimit { limit: Option<f64> },
+    // Green over the first `green` of every cycle, the cycles starting at `offset`
+    TrafficLight {
+        cycle: f64,
+        green: f64,
+        #[serde(default)]
+        offset: f64,
+    },
+    // Green extended by the cars going over its loop detector
+    ActuatedLight(Actuated),
+    StopSign,
+    // Lanes from `lane` up end here
+    LaneDrop { lane: usize },
+    // Cars of the inflows attached to the ramp enter here, on an acceleration lane
+    // right of the first lane over the `merge` that follows when it is not zero
+    OnRamp {
+        #[serde(default)]
+        merge: f64,
+    },
+    // Cars bound for the ramp keep to the first lane over the `diverge` before it,
+    // passing cars without a destination take it with probability `share`
+    OffRamp {
+        #[serde(default)]
+        share: f64,
+        #[serde(default)]
+        diverge: f64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fixture {
+    pub pos: f64,
+    #[serde(flatten)]
+    pub kind: FixtureKind,
+    // State of the controller of an actuated light
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signal: Option<Signal>,
+}
+
+impl Fixture {
+    pub fn new(pos: f64, kind: FixtureKind) -> Self {
+        Self { pos, kind, signal: None }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self.kind {
+            FixtureKind::SpeedLimit { limit: Some(limit) } if limit.is_nan() || limit <= 0f64 => Err(format!("limit {} should be positive", limit)),
+            FixtureKind::TrafficLight { cycle, green, .. } if !(0f64 < green && green <= cycle) => {
+                Err(format!("green {} should lie in (0, cycle {}]", green, cycle))
+            }
+            FixtureKind::LaneDrop { lane: 0 } => Err("the first lane cannot end".to_string()),
+            FixtureKind::OnRamp { merge } if merge.is_nan() || merge < 0f64 => Err(format!("merge {} should be non-negative", merge)),
+            FixtureKind::OffRamp { share, .. } if !(0f64..=1f64).contains(&share) => Err(format!("share {} should lie in [0, 1]", share)),
+            FixtureKind::OffRamp { diverge, .. } if diverge.is_nan() || diverge < 0f64 => Err(format!("diverge {} should be non-negative", diverge)),
+            FixtureKind::ActuatedLight(plan) => plan.validate(),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_green(&self, time: f64) -> bool {
+        match self.kind {
+            FixtureKind::TrafficLight { cycle, green, offset } => (time - offset).rem_euclid(cycle) < green,
+            FixtureKind::ActuatedLight(_) => self.signal.unwrap_or_default().is_green(),
+            _ => true,
+        }
+    }
+
+    pub fn merge_end(&self) -> Option<f64> {
+        // End of the acceleration lane of an on ramp that has one
+        match self.kind {
+            FixtureKind::OnRamp { merge } if merge > 0f64 => Some(self.pos + merge),
+            _ => None,
+        }
+    }
+
+    pub fn is_light(&self) -> bool {
+        matches!(self.kind, FixtureKind::TrafficLight { .. } | FixtureKind::ActuatedLight(_))
+    }
+
+    pub fn signal(&self) -> Option<&Signal> {
+        self.signal.as_ref()
+    }
+
+    pub fn actuate(&mut self, time: f64, called: bool) {
+        if let FixtureKind::ActuatedLight(plan) = &self.kind {
+            self.signal.get_or_insert_with(Signal::default).update(plan, time, called);
+        }
+    }
+
+    pub fn blocks(&self, id: FixtureId, car: &Car, lane: usize, time: f64) -> bool {
+        // Whether `car`, driving on `lane`, has to stop in front of the fixture
+        match self.kind {
+            FixtureKind::TrafficLight { .. } | FixtureKind::ActuatedLight(_) => !self.is_green(time) && car.cleared != Some(id),
+            FixtureKind::StopSign => car.cleared != Some(id),
+            FixtureKind::LaneDrop { lane: first } => lane >= first,
+            _ => false,
+        }
+    }
+
+    pub fn clear(&self, id: FixtureId, car: &mut Car, distance: f64, time: f64) {
+        // Cars too close to stop when the light is green go through, cars that stopped at a sign drive on
+        let v = car.vel[0];
+        let cleared = match self.kind {
+            FixtureKind::TrafficLight { .. } | FixtureKind::ActuatedLight(_) => {
+                let deceleration = car.limits.map_or(STOP_DECELERATION, |l| l.comfortable_deceleration);
+                self.is_green(time) && distance < v * v / (2f64 * deceleration)
+            }
+            FixtureKind::StopSign => v < STOP_SPEED && distance < STOP_RANGE,
+            _ => false,
+        };
+        if cleared {
+            car.cleared = Some(id);
+        }
+    }
+
+    pub fn pass(&self, car: &mut Car) {
+        // Called as `car` goes past the fixture. Cars are held at the end of a lane drop
+        // until they merge, none is moved across here.
+        if let FixtureKind::SpeedLimit { limit } = self.kind {
+            car.road_limit = limit;
+            car.max_speed = car.cruise_speed();
+        }
+        car.cleared = None;
+    }
+
+    pub fn closes(&self, lane: usize, pos: f64) -> bool {
+        matches!(self.kind, FixtureKind::LaneDrop { lane: first } if lane >= first && pos >= self.pos)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureFlag {
+    pos: Cartessian1D<f64>,
+    fixture: FixtureId,
+}
+
+impl FixtureFlag {
+    pub fn new(pos: f64, fixture: FixtureId) -> Self {
+        Self { pos: Cartessian1D::new([pos]), fixture }
+    }
+
+    pub fn fixture(&self) -> FixtureId {
+        self.fixture
+    }
+
+    pub fn pos(&self) -> &Cartessian1D<f64> {
+        &self.pos
+    }
+}
+
+pub fn stop_line(lane: usize, pos: f64) -> Car {
+    // Standing obstacle of no length that cars follow up to a stop line
+    let mut stop = Car::new(lane, 0f64, 0f64, 0f64, 0f64, 0f64);
+    stop.pos[0] = pos;
+    stop
+}
+
+// Fixtures in the order of the list, and for every item the first of them ahead of it
+pub struct Ahead {
+    fixtures: Vec<(FixtureId, f64)>,
+    next: Vec<usize>,
+    ring: Option<f64>,
+}
+
+impl Ahead {
+    pub fn of(&self, i: usize, x: f64) -> impl Iterator<Item = (FixtureId, f64)> + '_ {
+        // Fixtures ahead of the item i at `x` with their distance, nearest first, around the ring if there is one
+        let k = self.next[i];
+        let beyond = self.fixtures[k..].iter().map(move |&(id, pos)| (id, pos - x));
+        let (around, length) = match self.ring {
+            Some(length) => (&self.fixtures[..k], length),
+            None => (&self.fixtures[..0], 0f64),
+        };
+        beyond.chain(around.iter().map(move |&(id, pos)| (id, pos + length - x)))
+    }
+}
+
+pub fn ahead(traffic: &TrafficList, length: f64, periodic: bool) -> Ahead {
+    // One backward pass over the list, so that looking up the fixtures ahead of a car does not rescan it
+    let fixtures: Vec<(FixtureId, f64)> = traffic
+        .iter()
+        .filter_map(|item| match item {
+            TrafficItem::Fixture(f) => Some((f.fixture, f.pos[0])),
+            _ => None,
+        })
+        .collect();
+    let mut next = vec![0; traffic.len()];
+    let mut k = fixtures.len();
+    for (i, item) in traffic.items.iter().enumerate().rev() {
+        next[i] = k;
+        if let TrafficItem::Fixture(_) = item {
+            k -= 1;
+        }
+    }
+    Ahead { fixtures, next, ring: periodic.then_some(length) }
+}
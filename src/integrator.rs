@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IntegratorKind {
+    #[default]
+    Euler,
+    // Constant acceleration over the step, cars stop rather than roll backwards (Treiber and Kanagaraj, 2015)
+    Ballistic,
+    // Velocity Verlet, the velocity of the second force evaluation being predicted by Euler
+    Verlet,
+    Rk4,
+    // Heun steps with an Euler error estimate, as many as needed to cover the time step
+    Adaptive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Adaptive {
+    // Largest velocity error allowed in a single substep
+    pub tolerance: f64,
+    pub min_dt: f64,
+    // A substep covers at most this fraction of the time any car needs to close its gap
+    pub contact_fraction: f64,
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Self {
+            tolerance: 1e-3,
+            min_dt: 1e-4,
+            contact_fraction: 0.1,
+        }
+    }
+}
+
+impl Adaptive {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [("tolerance", self.tolerance), ("min_dt", self.min_dt), ("contact_fraction", self.contact_fraction)] {
+            if value.is_nan() || value <= 0f64 {
+                return Err(format!("{} {} should be positive", name, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn next_dt(&self, dt: f64, error: f64) -> f64 {
+        // Usual controller of a second order method, kept within a factor 5 of the last substep
+        if error <= 0f64 {
+            return 5f64 * dt;
+        }
+        dt * (0.9 * (self.tolerance / error).sqrt()).clamp(0.2, 5f64)
+    }
+}
+
+// State of every car as positions and velocities, in the order of the traffic list
+pub type State = (Vec<f64>, Vec<f64>);
+
+fn advance(x: &[f64], dx: &[f64], h: f64) -> Vec<f64> {
+    x.iter().zip(dx).map(|(x, dx)| x + h * dx).collect()
+}
+
+pub fn euler(x: &[f64], v: &[f64], a: &[f64], h: f64) -> State {
+    (advance(x, v, h), advance(v, a, h))
+}
+
+pub fn ballistic(x: &[f64], v: &[f64], a: &[f64], h: f64) -> State {
+    x.iter()
+        .zip(v)
+        .zip(a)
+        .map(|((x, v), a)| {
+            let v1 = v + a * h;
+            if v1 < 0f64 && *v >= 0f64 {
+                // The car stops within the step and stays there
+                (x - v * v / (2f64 * a), 0f64)
+            } else {
+                (x + 0.5 * (v + v1) * h, v1)
+            }
+        })
+        .unzip()
+}
+
+pub fn verlet<F: FnMut(&[f64], &[f64]) -> Vec<f64>>(x: &[f64], v: &[f64], a: &[f64], h: f64, mut acc: F) -> State {
+    let x1: Vec<f64> = (0..x.len()).map(|k| x[k] + v[k] * h + 0.5 * a[k] * h * h).collect();
+    let a1 = acc(&x1, &advance(v, a, h));
+    let v1 = (0..x.len()).map(|k| v[k] + 0.5 * (a[k] + a1[k]) * h).collect();
+    (x1, v1)
+}
+
+pub fn rk4<F: FnMut(&[f64], &[f64]) -> Vec<f64>>(x: &[f64], v: &[f64], a: &[f64], h: f64, mut acc: F) -> State {
+    let (x2, v2) = (advance(x, v, 0.5 * h), advance(v, a, 0.5 * h));
+    let a2 = acc(&x2, &v2);
+    let (x3, v3) = (advance(x, &v2, 0.5 * h), advance(v, &a2, 0.5 * h));
+    let a3 = acc(&x3, &v3);
+    let (x4, v4) = (advance(x, &v3, h), advance(v, &a3, h));
+    let a4 = acc(&x4, &v4);
+    let x1 = (0..x.len()).map(|k| x[k] + h / 6f64 * (v[k] + 2f64 * v2[k] + 2f64 * v3[k] + v4[k])).collect();
+    let v1 = (0..x.len()).map(|k| v[k] + h / 6f64 * (a[k] + 2f64 * a2[k] + 2f64 * a3[k] + a4[k])).collect();
+    (x1, v1)
+}
+
+pub fn heun<F: FnMut(&[f64], &[f64]) -> Vec<f64>>(x: &[f64], v: &[f64], a: &[f64], h: f64, mut acc: F) -> (State, f64) {
+    // Also returns the largest gap between the Heun and the Euler velocities
+    let (xe, ve) = euler(x, v, a, h);
+    let a1 = acc(&xe, &ve);
+    let x1 = (0..x.len()).map(|k| x[k] + 0.5 * (v[k] + ve[k]) * h).collect();
+    let v1: Vec<f64> = (0..x.len()).map(|k| v[k] + 0.5 * (a[k] + a1[k]) * h).collect();
+    let error = v1.iter().zip(ve.iter()).map(|(a, b)| (a - b).abs()).fold(0f64, f64::max);
+    ((x1, v1), error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    type Method = dyn Fn(&[f64], &[f64], &[f64], f64) -> State;
+
+    fn oscillator(x: &[f64], _v: &[f64]) -> Vec<f64> {
+        x.iter().map(|x| -x).collect()
+    }
+
+    #[test]
+    fn test_orders() {
+        // Harmonic oscillator over a quarter period : the error shrinks with the order of the method
+        let steps = 100;
+        let h = std::f64::consts::FRAC_PI_2 / steps as f64;
+        let run = |method: &Method| {
+            let (mut x, mut v) = (vec![1f64], vec![0f64]);
+            for _ in 0..steps {
+                let a = oscillator(&x, &v);
+                (x, v) = method(&x, &v, &a, h);
+            }
+            x[0].hypot(v[0] + 1f64)
+        };
+        let euler_error = run(&|x, v, a, h| euler(x, v, a, h));
+        let verlet_error = run(&|x, v, a, h| verlet(x, v, a, h, oscillator));
+        let rk4_error = run(&|x, v, a, h| rk4(x, v, a, h, oscillator));
+        assert!(euler_error > 1e-2);
+        assert!(verlet_error < 1e-4);
+        assert!(rk4_error < 1e-8);
+
+        // Braking to rest halfway through the step
+        let (x, v) = ballistic(&[0f64], &[1f64], &[-2f64], 1f64);
+        assert_abs_diff_eq!(x[0], 0.25, epsilon = 1e-12);
+        assert_eq!(v[0], 0f64);
+    }
+}
@@ -0,0 +1,297 @@
+use crate::model::{model_of, CarFollowingModel};
+use crate::{Car, TrafficItem, TrafficList};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Neighbors<'c> {
+    pub leader: Option<&'c Car>,
+    pub follower: Option<&'c Car>,
+    // Length of the ring the lane closes on, None on an open road
+    pub ring: Option<f64>,
+}
+
+pub trait LaneChangeRule {
+    // Incentive of moving `car` from the `current` lane into the `target` lane.
+    // None if the change is unsafe; changes are taken only when the incentive is positive.
+    // Accelerations come from `model` for cars without a model of their own.
+    fn incentive(&self, model: &dyn CarFollowingModel, car: &Car, current: Neighbors, target: Neighbors) -> Option<f64>;
+}
+
+pub fn distance(rear: &Car, front: &Car, ring: Option<f64>) -> f64 {
+    // Center to center distance from `rear` ahead to `front`, across the seam of a ring.
+    // A car alone on a ring lane follows itself one lap ahead.
+    let d = front.pos[0] - rear.pos[0];
+    match ring {
+        Some(length) if std::ptr::eq(rear, front) => length,
+        Some(length) => d.rem_euclid(length),
+        None => d,
+    }
+}
+
+pub fn acceleration(model: &dyn CarFollowingModel, car: &Car, leader: Option<&Car>, ring: Option<f64>) -> f64 {
+    let model = model_of(car, model);
+    match leader {
+        Some(leader) => model.acceleration(car, leader, distance(car, leader, ring)),
+        None => model.free_acceleration(car),
+    }
+}
+
+pub fn gap_accepted(car: &Car, leader_gap: Option<f64>, follower: Option<(&Car, f64)>) -> bool {
+    // Both gaps around `car` should exceed the safe distance of the car behind
+    leader_gap.is_none_or(|gap| gap >= car.safe_distance(true)) && follower.is_none_or(|(f, gap)| gap >= f.safe_distance(true))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mobil {
+    pub politeness: f64,
+    pub threshold: f64,
+    pub max_braking: f64,
+    pub safety: f64,
+}
+
+impl Default for Mobil {
+    fn default() -> Self {
+        Self {
+            politeness: 0.5,
+            threshold: 0.1,
+            max_braking: 4.0,
+            safety: 0.5,
+        }
+    }
+}
+
+impl Mobil {
+    pub fn new(politeness: f64, threshold: f64, max_braking: f64, safety: f64) -> Self {
+        Self { politeness, threshold, max_braking, safety }
+    }
+
+    fn is_safe(&self, model: &dyn CarFollowingModel, car: &Car, target: Neighbors) -> bool {
+        // Gaps in the target lane should exceed a fraction of the safe distances
+        if let Some(leader) = target.leader {
+            if distance(car, leader, target.ring) < self.safety * car.safe_distance(true) {
+                return false;
+            }
+        }
+        if let Some(follower) = target.follower {
+            if distance(follower, car, target.ring) < self.safety * follower.safe_distance(true) {
+                return false;
+            }
+            if acceleration(model, follower, Some(car), target.ring) < -self.max_braking {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl LaneChangeRule for Mobil {
+    fn incentive(&self, model: &dyn CarFollowingModel, car: &Car, current: Neighbors, target: Neighbors) -> Option<f64> {
+        if !self.is_safe(model, car, target) {
+            return None;
+        }
+
+        let ring = current.ring;
+        let own = acceleration(model, car, target.leader, ring) - acceleration(model, car, current.leader, ring);
+        let new_follower = target
+            .follower
+            .map_or(0f64, |f| acceleration(model, f, Some(car), ring) - acceleration(model, f, target.leader, ring));
+        let old_follower = current
+            .follower
+            .map_or(0f64, |f| acceleration(model, f, current.leader, ring) - acceleration(model, f, Some(car), ring));
+
+        Some(own + self.politeness * (new_follower + old_follower) - self.threshold)
+    }
+}
+
+// Rules a simulation can use, kept as data so that a run can be saved with its rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LaneChange {
+    Mobil(Mobil),
+}
+
+impl From<Mobil> for LaneChange {
+    fn from(rule: Mobil) -> Self {
+        LaneChange::Mobil(rule)
+    }
+}
+
+impl LaneChangeRule for LaneChange {
+    fn incentive(&self, model: &dyn CarFollowingModel, car: &Car, current: Neighbors, target: Neighbors) -> Option<f64> {
+        match self {
+            LaneChange::Mobil(rule) => rule.incentive(model, car, current, target),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LaneIndex {
+    // Item indices of the cars on every lane, in the order of the traffic list.
+    // Cars merging from an on ramp drive on an extra lane past the last one.
+    // Ordered sets keep a lane change at O(log N), however many cars share the lane.
+    lanes: Vec<BTreeSet<usize>>,
+    // Lane of every item, None for flags
+    slots: Vec<Option<usize>>,
+}
+
+impl LaneIndex {
+    pub fn new(traffic: &TrafficList, lanes: usize) -> Self {
+        let mut index = Self {
+            lanes: vec![BTreeSet::new(); lanes + 1],
+            slots: vec![None; traffic.len()],
+        };
+        for (i, item) in traffic.iter().enumerate() {
+            if let TrafficItem::Car(c) = item {
+                let lane = if c.merging.is_some() { lanes } else { c.lane };
+                if lane >= index.lanes.len() {
+                    index.lanes.resize(lane + 1, BTreeSet::new());
+                }
+                index.slots[i] = Some(lane);
+                index.lanes[lane].insert(i);
+            }
+        }
+        index
+    }
+
+    pub fn lane(&self, lane: usize) -> impl Iterator<Item = usize> + '_ {
+        self.lanes.get(lane).into_iter().flatten().copied()
+    }
+
+    pub fn leader(&self, i: usize, periodic: bool) -> Option<usize> {
+        // With a periodic boundary the first car of the lane leads the last one
+        let lane = &self.lanes[(*self.slots.get(i)?)?];
+        match lane.range(i + 1..).next() {
+            Some(&j) => Some(j),
+            None if periodic && lane.len() > 1 => lane.first().copied(),
+            None => None,
+        }
+    }
+
+    pub fn follower(&self, i: usize, periodic: bool) -> Option<usize> {
+        let lane = &self.lanes[(*self.slots.get(i)?)?];
+        match lane.range(..i).next_back() {
+            Some(&j) => Some(j),
+            None if periodic && lane.len() > 1 => lane.last().copied(),
+            None => None,
+        }
+    }
+
+    pub fn around(&self, lane: usize, i: usize, periodic: bool) -> (Option<usize>, Option<usize>) {
+        // Nearest cars on `lane` ahead of and behind the item i.
+        // With a periodic boundary they are looked for past the seam as well.
+        let Some(lane) = self.lanes.get(lane) else { return (None, None) };
+        let leader = lane.range(i + 1..).next().copied();
+        let follower = lane.range(..i).next_back().copied();
+        if !periodic {
+            return (leader, follower);
+        }
+        (
+            leader.or_else(|| lane.iter().copied().find(|&j| j != i)),
+            follower.or_else(|| lane.iter().rev().copied().find(|&j| j != i)),
+        )
+    }
+
+    pub fn move_car(&mut self, i: usize, to: usize) {
+        // Keeps the index valid after the car i changed lane
+        let from = match self.slots.get(i) {
+            Some(&Some(lane)) => lane,
+            _ => panic!("Invalid lane change : item {} is not a car", i),
+        };
+        self.lanes[from].remove(&i);
+        if to >= self.lanes.len() {
+            self.lanes.resize(to + 1, BTreeSet::new());
+        }
+        self.lanes[to].insert(i);
+        self.slots[i] = Some(to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::LennardJones;
+    use crate::{CamId, SpeedCam};
+    use approx::assert_abs_diff_eq;
+    use moldybrody::prelude::*;
+
+    fn car_at(lane: usize, pos: f64, vel: f64) -> Car {
+        let mut car = Car::new(lane, 1f64, 10f64, 1f64, 0f64, 10f64);
+        car.pos[0] = pos;
+        car.vel[0] = vel;
+        car
+    }
+
+    #[test]
+    fn test_mobil_incentive() {
+        let mobil = Mobil::default();
+        let car = car_at(0, 100f64, 8f64);
+        let slow = car_at(0, 115f64, 2f64);
+
+        let blocked = Neighbors { leader: Some(&slow), follower: None, ring: None };
+        let free = Neighbors::default();
+        assert!(mobil.incentive(&LennardJones, &car, blocked, free).unwrap() > 0f64);
+        assert!(mobil.incentive(&LennardJones, &car, free, blocked).unwrap() < 0f64);
+
+        // On a ring the leader past the seam is as close as it looks
+        let seam = car_at(0, 5f64, 2f64);
+        let last = car_at(0, 990f64, 8f64);
+        let ring = Some(1000f64);
+        assert_abs_diff_eq!(acceleration(&LennardJones, &last, Some(&seam), ring), acceleration(&LennardJones, &car, Some(&slow), None), epsilon = 1e-12);
+        let blocked = Neighbors { leader: Some(&seam), follower: None, ring };
+        let free = Neighbors { ring, ..Neighbors::default() };
+        assert!(mobil.incentive(&LennardJones, &last, blocked, free).unwrap() > 0f64);
+    }
+
+    #[test]
+    fn test_lane_index() {
+        let cam = SpeedCam::new(Cartessian1D::new([50f64]), 5f64, 10f64, false);
+        let (open, close) = cam.flags(CamId(0));
+        let items = vec![
+            TrafficItem::Car(car_at(0, 10f64, 0f64)),
+            TrafficItem::Car(car_at(1, 20f64, 0f64)),
+            TrafficItem::Flag(open),
+            TrafficItem::Car(car_at(0, 45f64, 0f64)),
+            TrafficItem::Flag(close),
+            TrafficItem::Car(car_at(0, 60f64, 0f64)),
+        ];
+        let traffic = TrafficList::new(items);
+        let mut index = LaneIndex::new(&traffic, 2);
+
+        assert_eq!(index.lane(0).collect::<Vec<_>>(), vec![0, 3, 5]);
+        assert_eq!(index.leader(3, false), Some(5));
+        assert_eq!(index.leader(5, false), None);
+        assert_eq!(index.leader(5, true), Some(0));
+        assert_eq!(index.follower(0, true), Some(5));
+        assert_eq!(index.leader(1, true), None);
+        assert_eq!(index.leader(2, true), None);
+        assert_eq!(index.around(1, 3, false), (None, Some(1)));
+        assert_eq!(index.around(0, 1, false), (Some(3), Some(0)));
+        assert_eq!(index.around(1, 3, true), (Some(1), Some(1)));
+        assert_eq!(index.around(0, 5, true), (Some(0), Some(3)));
+
+        index.move_car(3, 1);
+        assert_eq!(index.lane(0).collect::<Vec<_>>(), vec![0, 5]);
+        assert_eq!(index.lane(1).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(index.leader(0, false), Some(5));
+        assert_eq!(index.follower(3, false), Some(1));
+    }
+
+    #[test]
+    fn test_mobil_safety() {
+        let mobil = Mobil::default();
+        let car = car_at(0, 100f64, 8f64);
+        let slow = car_at(0, 115f64, 2f64);
+        let close = car_at(1, 97f64, 10f64);
+
+        let blocked = Neighbors { leader: Some(&slow), follower: None, ring: None };
+        let target = Neighbors { leader: None, follower: Some(&close), ring: None };
+        assert!(mobil.incentive(&LennardJones, &car, blocked, target).is_none());
+
+        // Merging needs the full safe distances
+        assert!(!gap_accepted(&car, None, Some((&close, 3f64))));
+        assert!(!gap_accepted(&car, Some(15f64), None));
+        assert!(gap_accepted(&car, Some(40f64), Some((&close, 40f64))));
+    }
+}
@@ -1,70 +1,559 @@
 use moldybrody::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 
-#[derive(Debug, Clone)]
-pub enum TrafficItem<'a> {
+pub mod anticipation;
+pub mod cellular;
+pub mod collision;
+pub mod fundamental;
+pub mod infrastructure;
+pub mod inflow;
+pub mod integrator;
+pub mod lane;
+pub mod limits;
+pub mod measure;
+pub mod model;
+pub mod noise;
+pub mod population;
+pub mod recorder;
+pub mod scenario;
+pub mod signal;
+pub mod simulation;
+pub mod vehicle;
+pub mod violation;
+
+pub use anticipation::Anticipation;
+pub use collision::{CollisionPolicy, Crash};
+pub use inflow::{Exit, Inflow};
+pub use infrastructure::{Fixture, FixtureFlag, FixtureId, FixtureKind};
+pub use integrator::IntegratorKind;
+pub use limits::{EmergencyBraking, Limits};
+pub use measure::{LoopDetector, Measurement, Segment, SegmentMonitor};
+pub use model::{CarFollowingModel, Model};
+pub use noise::Noise;
+pub use population::DriverMix;
+pub use recorder::{Observer, Recorder, Sample};
+pub use scenario::Scenario;
+pub use signal::{Actuated, Signal};
+pub use simulation::Simulation;
+pub use vehicle::VehicleClass;
+pub use violation::{Violation, ViolationLog};
+
+// Nearly every item is a car, boxing them would only add an indirection
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrafficItem {
     Car(Car),
-    Flag(SpeedCamFlag<'a>)
+    Flag(SpeedCamFlag),
+    Fixture(FixtureFlag),
 }
 
-impl<'a> TrafficItem<'a> {
+impl TrafficItem {
     pub fn pos(&self) -> &Cartessian1D<f64> {
         match self{
             TrafficItem::Car(c) => &c.pos,
-            TrafficItem::Flag(f) => &f.pos
+            TrafficItem::Flag(f) => &f.pos,
+            TrafficItem::Fixture(f) => f.pos(),
         }
     }
+}
 
-    pub fn set_max_speed(&self, other : &mut Self) {
-        match (self, other) {
-            (TrafficItem::Flag(f), TrafficItem::Car(c)) => {
-                f.set_max_speed(c);
-            }
-            _ => unreachable!(),
-        }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CarId(pub usize);
+
+impl fmt::Display for CarId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
-pub struct TrafficList<'a> {
-    items : Vec<TrafficItem<'a>>,
+// Index of a camera on its road
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CamId(pub usize);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "TrafficListData")]
+pub struct TrafficList {
+    items : Vec<TrafficItem>,
+    sections : Vec<SectionRecord>,
+    next_id : usize,
 }
 
-impl<'a> TrafficList<'a>{
+impl TrafficList{
     pub fn len(&self) -> usize {
         self.items.len()
     }
 
-    pub fn new(items : Vec<TrafficItem<'a>>) -> Self{
-        Self{
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn new(items : Vec<TrafficItem>) -> Self{
+        match Self::try_new(items) {
+            Ok(list) => list,
+            Err(message) => panic!("Invalid traffic list : {}", message),
+        }
+    }
+
+    pub fn try_new(items : Vec<TrafficItem>) -> Result<Self, String>{
+        // Cars keep the identity they came with, the others are numbered in list order
+        let mut seen = BTreeSet::new();
+        for item in items.iter() {
+            if let TrafficItem::Car(Car { id : Some(id), .. }) = item {
+                if !seen.insert(*id) {
+                    return Err(format!("car id {} is used twice", id));
+                }
+            }
+        }
+        let next_id = items.iter().filter_map(|item| match item {
+            TrafficItem::Car(Car { id : Some(id), .. }) => Some(id.0 + 1),
+            _ => None,
+        }).max().unwrap_or(0);
+
+        let mut list = Self{
             items,
+            sections : vec![],
+            next_id,
+        };
+        for i in 0..list.len() {
+            if let TrafficItem::Car(c) = &mut list.items[i] {
+                if c.id.is_none() {
+                    c.id = Some(CarId(list.next_id));
+                    list.next_id += 1;
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    pub fn position(&self, id : CarId) -> Option<usize> {
+        self.items.iter().position(|item| matches!(item, TrafficItem::Car(c) if c.id == Some(id)))
+    }
+
+    pub fn car(&self, id : CarId) -> Option<&Car> {
+        match self.items.get(self.position(id)?) {
+            Some(TrafficItem::Car(c)) => Some(c),
+            _ => None,
+        }
+    }
+
+    pub fn car_mut(&mut self, id : CarId) -> Option<&mut Car> {
+        let i = self.position(id)?;
+        match self.items.get_mut(i) {
+            Some(TrafficItem::Car(c)) => Some(c),
+            _ => None,
+        }
+    }
+
+    pub fn remove_car(&mut self, id : CarId) -> Option<Car> {
+        match self.items.remove(self.position(id)?) {
+            TrafficItem::Car(c) => Some(c),
+            _ => unreachable!(),
         }
     }
 
-    pub fn check_switch(&mut self, i : usize, speed_limit : f64){
+    pub fn check_overtake(&mut self, i : usize) -> bool{
+        // Cars on different lanes pass each other without touching any flag
         if i == 0 || i >= self.len(){
             panic!("Invalid index input : index {} should be in 1..{}", i, self.len());
         }
 
-        if let TrafficItem::Flag(f) = self.items[i].clone(){
-            if let TrafficItem::Car(mut c) = self.items[i - 1].clone(){
+        if let (TrafficItem::Car(rear), TrafficItem::Car(front)) = (&self.items[i - 1], &self.items[i]){
+            if front.pos[0] < rear.pos[0]{
+                self.items.swap(i - 1, i);
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn sections(&self) -> &[SectionRecord] {
+        &self.sections
+    }
+
+    pub fn get(&self, i : usize) -> Option<&TrafficItem> {
+        self.items.get(i)
+    }
+
+    pub fn get_mut(&mut self, i : usize) -> Option<&mut TrafficItem> {
+        self.items.get_mut(i)
+    }
+
+    pub fn cars(&self) -> impl Iterator<Item = &Car> {
+        self.items.iter().filter_map(|item| match item {
+            TrafficItem::Car(c) => Some(c),
+            _ => None,
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TrafficItem> {
+        self.items.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut TrafficItem> {
+        self.items.iter_mut()
+    }
+}
+
+// Serialized form of a TrafficList, which goes through the same checks as TrafficList::try_new when read back
+#[derive(Deserialize)]
+struct TrafficListData {
+    items : Vec<TrafficItem>,
+    #[serde(default)]
+    sections : Vec<SectionRecord>,
+    #[serde(default)]
+    next_id : usize,
+}
+
+impl TryFrom<TrafficListData> for TrafficList {
+    type Error = String;
+
+    fn try_from(data : TrafficListData) -> Result<Self, String> {
+        let mut list = Self::try_new(data.items)?;
+        list.sections = data.sections;
+        list.next_id = list.next_id.max(data.next_id);
+        Ok(list)
+    }
+}
+
+// Cameras and the traffic going past them. Flags name their camera by its index, so a road
+// owns all of its state and can be cloned, sent to another thread or written to disk.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "RoadData")]
+pub struct Road {
+    cams : Vec<SpeedCam>,
+    #[serde(default)]
+    fixtures : Vec<Fixture>,
+    traffic : TrafficList,
+    // Cars that went past an off ramp since the last call to `take_departures`
+    #[serde(default)]
+    departures : Vec<(CarId, FixtureId)>,
+}
+
+// Road as written on disk, checked by `Road::validate` before use
+#[derive(Deserialize)]
+struct RoadData {
+    cams : Vec<SpeedCam>,
+    #[serde(default)]
+    fixtures : Vec<Fixture>,
+    traffic : TrafficList,
+    #[serde(default)]
+    departures : Vec<(CarId, FixtureId)>,
+}
+
+impl TryFrom<RoadData> for Road {
+    type Error = String;
+
+    fn try_from(data : RoadData) -> Result<Self, String> {
+        let road = Self { cams : data.cams, fixtures : data.fixtures, traffic : data.traffic, departures : data.departures };
+        road.validate()?;
+        Ok(road)
+    }
+}
+
+impl Road {
+    pub fn new(cams : Vec<SpeedCam>, traffic : TrafficList) -> Result<Self, String> {
+        if traffic.iter().any(|item| matches!(item, TrafficItem::Fixture(_))) {
+            return Err("fixtures are added with Road::with_fixtures".to_string());
+        }
+        let road = Self { cams, fixtures : vec![], traffic, departures : vec![] };
+        road.validate()?;
+        Ok(road)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        // Every flag and departure has to name a camera or fixture of this road
+        for item in self.traffic.iter() {
+            match item {
+                TrafficItem::Flag(f) if f.cam.0 >= self.cams.len() => {
+                    return Err(format!("flag at {} refers to camera {} of {}", f.pos[0], f.cam.0, self.cams.len()));
+                }
+                TrafficItem::Fixture(f) if f.fixture().0 >= self.fixtures.len() => {
+                    return Err(format!("fixture flag at {} refers to fixture {} of {}", f.pos()[0], f.fixture().0, self.fixtures.len()));
+                }
+                _ => {}
+            }
+        }
+        for fixture in &self.fixtures {
+            fixture.validate()?;
+        }
+        if let Some((car, fixture)) = self.departures.iter().find(|(_, f)| f.0 >= self.fixtures.len()) {
+            return Err(format!("car {} departed at fixture {} of {}", car.0, fixture.0, self.fixtures.len()));
+        }
+        Ok(())
+    }
+
+    pub fn with_fixtures(mut self, fixtures : Vec<Fixture>) -> Result<Self, String> {
+        // Cars are put back one by one so that those beyond a sign take its limit
+        if fixtures.is_empty() {
+            return Ok(self);
+        }
+        for fixture in fixtures {
+            fixture.validate()?;
+            let id = FixtureId(self.fixtures.len());
+            let items = &mut self.traffic.items;
+            let i = items.iter().position(|item| item.pos()[0] > fixture.pos).unwrap_or(items.len());
+            items.insert(i, TrafficItem::Fixture(FixtureFlag::new(fixture.pos, id)));
+            self.fixtures.push(fixture);
+        }
+        let cars : Vec<Car> = self.traffic.cars().cloned().collect();
+        self.traffic.items.retain(|item| !matches!(item, TrafficItem::Car(_)));
+        for car in cars {
+            self.insert_car(car);
+        }
+        Ok(self)
+    }
+
+    pub fn fixtures(&self) -> &[Fixture] {
+        &self.fixtures
+    }
+
+    pub fn fixture(&self, id : FixtureId) -> &Fixture {
+        &self.fixtures[id.0]
+    }
+
+    pub fn take_departures(&mut self) -> Vec<(CarId, FixtureId)> {
+        std::mem::take(&mut self.departures)
+    }
+
+    pub fn from_cars(cars : Vec<Car>, cams : Vec<SpeedCam>) -> Result<Self, String> {
+        // Cars and the flags of every camera, sorted by position
+        let mut items : Vec<TrafficItem> = cars.into_iter().map(TrafficItem::Car).collect();
+        for (k, cam) in cams.iter().enumerate() {
+            let (open, close) = cam.flags(CamId(k));
+            items.push(TrafficItem::Flag(open));
+            items.push(TrafficItem::Flag(close));
+        }
+        if let Some(k) = items.iter().position(|item| item.pos()[0].is_nan()) {
+            return Err(format!("item {} has no position", k));
+        }
+        items.sort_by(|a, b| a.pos()[0].total_cmp(&b.pos()[0]));
+        Self::new(cams, TrafficList::try_new(items)?)
+    }
+
+    pub fn cams(&self) -> &[SpeedCam] {
+        &self.cams
+    }
+
+    pub fn cam(&self, id : CamId) -> &SpeedCam {
+        &self.cams[id.0]
+    }
+
+    pub fn traffic(&self) -> &TrafficList {
+        &self.traffic
+    }
+
+    pub fn traffic_mut(&mut self) -> &mut TrafficList {
+        &mut self.traffic
+    }
+
+    pub fn violations(&self) -> ViolationLog {
+        let mut log = ViolationLog::new();
+        for cam in self.cams.iter() {
+            log.extend(cam.violations());
+        }
+        log
+    }
+
+    pub fn insert_car(&mut self, mut car : Car) -> CarId {
+        // The car is placed by position and gets the speed limit of a zone it lands in
+        let id = match car.id {
+            Some(id) if self.traffic.position(id).is_none() => {
+                self.traffic.next_id = self.traffic.next_id.max(id.0 + 1);
+                id
+            }
+            _ => {
+                self.traffic.next_id += 1;
+                CarId(self.traffic.next_id - 1)
+            }
+        };
+        car.id = Some(id);
+
+        let items = &mut self.traffic.items;
+        let i = items.iter().position(|item| item.pos()[0] > car.pos[0]).unwrap_or(items.len());
+        let last_sign = items[..i].iter().rev().find_map(|item| match item {
+            TrafficItem::Fixture(f) => match self.fixtures[f.fixture().0].kind {
+                FixtureKind::SpeedLimit { limit } => Some(limit),
+                _ => None,
+            },
+            _ => None,
+        });
+        if let Some(limit) = last_sign {
+            car.road_limit = limit;
+            car.max_speed = car.cruise_speed();
+        }
+        let last_flag = items[..i].iter().rev().find_map(|item| match item {
+            TrafficItem::Flag(f) => Some(f),
+            _ => None,
+        });
+        if let Some(f) = last_flag {
+            f.apply_limit(&self.cams[f.cam.0], &mut car);
+        }
+        items.insert(i, TrafficItem::Car(car));
+        id
+    }
+
+
+    pub fn check_switch(&mut self, i : usize, time : f64) -> bool{
+        let items = &mut self.traffic.items;
+        if i == 0 || i >= items.len(){
+            panic!("Invalid index input : index {} should be in 1..{}", i, items.len());
+        }
+
+        if let TrafficItem::Flag(f) = items[i].clone(){
+            if let TrafficItem::Car(mut c) = items[i - 1].clone(){
                 if f.pos() < c.pos(){
-                    f.set_max_speed(&mut c);
+                    let cam = &mut self.cams[f.cam.0];
+                    f.set_max_speed(cam, &mut c);
                     if !f.status{
-                        assert!(c.vel[0] < speed_limit * 1.1);
+                        if cam.check_average {
+                            if let Some(elapsed) = c.section_times.remove(&f.cam) {
+                                let record = SectionRecord::new(cam, &c, elapsed);
+                                cam.measure(&c, record.average_speed, time, true);
+                                self.traffic.sections.push(record);
+                            }
+                        } else {
+                            cam.measure(&c, c.vel[0], time, false);
+                        }
+                    }
+                    items[i] = TrafficItem::Car(c);
+                    items[i - 1] = TrafficItem::Flag(f);
+                    return true;
+                }
+            }
+        }
+        if let TrafficItem::Fixture(f) = items[i].clone(){
+            if let TrafficItem::Car(mut c) = items[i - 1].clone(){
+                if f.pos() < c.pos(){
+                    let fixture = &self.fixtures[f.fixture().0];
+                    fixture.pass(&mut c);
+                    // A sign inside a camera zone cannot lift the camera's limit
+                    let zone = items[..i - 1].iter().rev().find_map(|item| match item {
+                        TrafficItem::Flag(flag) => Some(flag),
+                        _ => None,
+                    });
+                    if let Some(zone) = zone.filter(|flag| flag.status) {
+                        let limit = self.cams[zone.cam.0].limit_for(c.class);
+                        c.max_speed = c.max_speed.min((1.0 + c.behavior) * limit);
+                    }
+                    if matches!(fixture.kind, FixtureKind::OffRamp { .. }) {
+                        self.departures.push((c.listed_id(), f.fixture()));
                     }
-                    self.items[i] = TrafficItem::Car(c);
-                    self.items[i - 1] = TrafficItem::Flag(f);
+                    items[i] = TrafficItem::Car(c);
+                    items[i - 1] = TrafficItem::Fixture(f);
+                    return true;
                 }
             }
         }
+        false
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = &TrafficItem<'a>> {
-        self.items.iter()
+    pub fn check_back(&mut self, i : usize) -> bool{
+        // A car that rolled back behind a flag or a fixture goes back over it : it leaves the section it
+        // had entered, and gets the limits of the signs and zones behind it again. Nothing is measured.
+        let items = &mut self.traffic.items;
+        if i == 0 || i >= items.len(){
+            panic!("Invalid index input : index {} should be in 1..{}", i, items.len());
+        }
+
+        let mut car = match &items[i] {
+            TrafficItem::Car(c) if c.pos[0] < items[i - 1].pos()[0] && !matches!(items[i - 1], TrafficItem::Car(_)) => c.clone(),
+            _ => return false,
+        };
+        items.swap(i - 1, i);
+        match &items[i] {
+            TrafficItem::Flag(f) if f.status => {
+                car.section_times.remove(&f.cam);
+            }
+            TrafficItem::Fixture(f) if matches!(self.fixtures[f.fixture().0].kind, FixtureKind::SpeedLimit { .. }) => {
+                car.road_limit = items[..i - 1].iter().rev().find_map(|item| match item {
+                    TrafficItem::Fixture(f) => match self.fixtures[f.fixture().0].kind {
+                        FixtureKind::SpeedLimit { limit } => Some(limit),
+                        _ => None,
+                    },
+                    _ => None,
+                }).flatten();
+            }
+            _ => {}
+        }
+        car.max_speed = car.cruise_speed();
+        let last_flag = items[..i - 1].iter().rev().find_map(|item| match item {
+            TrafficItem::Flag(f) => Some(f),
+            _ => None,
+        });
+        if let Some(f) = last_flag {
+            f.apply_limit(&self.cams[f.cam.0], &mut car);
+        }
+        items[i - 1] = TrafficItem::Car(car);
+        true
     }
 
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut TrafficItem<'a>> {
-        self.items.iter_mut()
+    pub fn wrap_around(&mut self, wrapped : &[CarId], length : f64, time : f64){
+        // Cars which crossed the periodic boundary first go past the items left between them and the end
+        // of the road, as if they were one length further, then the list is rotated to bring them to its head.
+        let mut n = 0;
+        for &id in wrapped {
+            let Some(mut i) = self.traffic.position(id) else { continue };
+            if let Some(TrafficItem::Car(c)) = self.traffic.get_mut(i) {
+                c.pos[0] += length;
+            }
+            while i + 1 < self.traffic.len() {
+                let switched = match &self.traffic.items[i + 1] {
+                    TrafficItem::Flag(_) | TrafficItem::Fixture(_) => self.check_switch(i + 1, time),
+                    TrafficItem::Car(_) => self.traffic.check_overtake(i + 1),
+                };
+                if !switched {
+                    break;
+                }
+                i += 1;
+            }
+            n += 1;
+        }
+        let len = self.traffic.len();
+        for item in self.traffic.items[len - n..].iter_mut() {
+            if let TrafficItem::Car(c) = item {
+                c.pos[0] -= length;
+            }
+        }
+        self.traffic.items.rotate_right(n);
+    }
+
+    pub fn wrap_backward(&mut self, reversed : &[CarId]){
+        // Cars which rolled back over the start of a ring drove no lap : they are put back among the
+        // items at the end of the road without going past any flag or fixture on the way.
+        for &id in reversed {
+            let Some(car) = self.traffic.remove_car(id) else { continue };
+            let i = self.traffic.items.iter().rposition(|item| item.pos()[0] <= car.pos[0]).map_or(0, |k| k + 1);
+            self.traffic.items.insert(i, TrafficItem::Car(car));
+        }
+    }
+
+    pub fn reorder(&mut self, time : f64){
+        // Insertion sort : every swap of a car over a flag or a fixture goes through check_switch,
+        // or through check_back for a car that rolled back over it
+        for i in 1..self.traffic.len(){
+            let mut j = i;
+            while j > 0 {
+                let switched = match &self.traffic.items[j] {
+                    TrafficItem::Flag(_) | TrafficItem::Fixture(_) => self.check_switch(j, time),
+                    TrafficItem::Car(_) => self.traffic.check_overtake(j) || self.check_back(j),
+                };
+                if !switched {
+                    break;
+                }
+                j -= 1;
+            }
+        }
+    }
+}
+
+impl TryFrom<TrafficList> for Road {
+    type Error = String;
+
+    fn try_from(traffic : TrafficList) -> Result<Self, String> {
+        Self::new(vec![], traffic)
     }
 }
 
@@ -72,6 +561,8 @@ impl<'a> TrafficList<'a>{
 
 #[derive(Debug, Serialize, Deserialize, State, Clone)]
 pub struct Car {
+    #[serde(default)]
+    id: Option<CarId>,
     pos: Cartessian1D<f64>,
     vel: Cartessian1D<f64>,
     lane: usize,
@@ -81,11 +572,49 @@ pub struct Car {
     mass: f64,
     behavior: f64,
     own_max_speed : f64,
+    // Time spent so far in each section control the car is in
+    #[serde(default)]
+    section_times : BTreeMap<CamId, f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model : Option<Model>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    noise : Option<Noise>,
+    #[serde(default)]
+    noise_state : f64,
+    #[serde(default)]
+    entry_time : Option<f64>,
+    #[serde(default)]
+    class : VehicleClass,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    limits : Option<Limits>,
+    #[serde(default)]
+    braking : bool,
+    // Stopped for good after a crash
+    #[serde(default)]
+    blocked : bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    anticipation : Option<Anticipation>,
+    // Last camera in sight and whether the driver noticed it
+    #[serde(default)]
+    noticed : Option<(CamId, bool)>,
+    // Limit of the last speed sign passed
+    #[serde(default)]
+    road_limit : Option<f64>,
+    // Light or stop sign ahead the car may go through
+    #[serde(default)]
+    cleared : Option<FixtureId>,
+    // Off ramp the driver leaves by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    destination : Option<FixtureId>,
+    // On ramp whose acceleration lane the car is still on
+    #[serde(default)]
+    merging : Option<FixtureId>,
 }
 
 impl Car {
     pub fn new(lane: usize, size: f64, max_speed: f64, drift: f64, behavior: f64, own_max_speed: f64) -> Self {
         Self {
+            id: None,
             pos: Cartessian1D { coord: [0f64] },
             vel: Cartessian1D { coord: [0f64] },
             lane,
@@ -95,9 +624,121 @@ impl Car {
             mass: 1f64,
             behavior,
             own_max_speed,
+            section_times: BTreeMap::new(),
+            model: None,
+            noise: None,
+            noise_state: 0f64,
+            entry_time: None,
+            class: VehicleClass::Car,
+            limits: None,
+            braking: false,
+            blocked: false,
+            anticipation: None,
+            noticed: None,
+            road_limit: None,
+            cleared: None,
+            destination: None,
+            merging: None,
         }
     }
 
+    pub fn with_class(mut self, class: VehicleClass) -> Self {
+        // Takes the mass, capabilities and lane restriction of the preset, size and speeds are left as given.
+        // The drift is a force, it grows with the mass so that the car keeps its acceleration.
+        let preset = class.preset();
+        self.class = class;
+        self.drift *= preset.mass / self.mass;
+        self.mass = preset.mass;
+        self.limits = Some(preset.limits);
+        self
+    }
+
+    pub fn class(&self) -> VehicleClass {
+        self.class
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    pub fn limits(&self) -> Option<&Limits> {
+        self.limits.as_ref()
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+    }
+
+    pub fn allows_lane(&self, lane: usize) -> bool {
+        self.class.allows_lane(lane)
+    }
+
+    pub fn with_anticipation(mut self, anticipation: Anticipation) -> Self {
+        self.anticipation = Some(anticipation);
+        self
+    }
+
+    pub fn anticipation(&self) -> Option<&Anticipation> {
+        self.anticipation.as_ref()
+    }
+
+    pub fn with_destination(mut self, ramp: FixtureId) -> Self {
+        self.destination = Some(ramp);
+        self
+    }
+
+    pub fn destination(&self) -> Option<FixtureId> {
+        self.destination
+    }
+
+    pub fn merging(&self) -> Option<FixtureId> {
+        self.merging
+    }
+
+    pub fn with_model(mut self, model: Model) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    pub fn model(&self) -> Option<&Model> {
+        self.model.as_ref()
+    }
+
+    pub fn with_noise(mut self, noise: Noise) -> Self {
+        self.noise = Some(noise);
+        self
+    }
+
+    pub fn noise(&self) -> Option<&Noise> {
+        self.noise.as_ref()
+    }
+
+    pub fn entry_time(&self) -> Option<f64> {
+        self.entry_time
+    }
+
+    pub fn id(&self) -> Option<CarId> {
+        // A car gets its identity when it is put in a TrafficList
+        self.id
+    }
+
+    pub(crate) fn listed_id(&self) -> CarId {
+        self.id.expect("cars of a TrafficList always have an identity")
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.vel[0]
+    }
+
+    pub fn lane(&self) -> usize {
+        self.lane
+    }
+
+    pub fn max_speed(&self) -> f64 {
+        self.max_speed
+    }
+
     pub fn drift_force(&self) -> Cartessian1D<f64> {
         Cartessian1D::new([self.drift * (self.max_speed - self.vel[0]).tanh()])
     }
@@ -106,6 +747,14 @@ impl Car {
         self.max_speed = (1.0 + self.behavior) * speed_limit;
     }
 
+    pub fn cruise_speed(&self) -> f64 {
+        // Target outside camera zones, the driver keeping to its own tolerance of the signed limit
+        match self.road_limit {
+            Some(limit) => self.own_max_speed.min((1.0 + self.behavior) * limit),
+            None => self.own_max_speed,
+        }
+    }
+
     pub fn safe_distance(&self, dir: bool) -> f64 {
         // dir == true : in front of self
         let v = self.vel[0];
@@ -117,70 +766,173 @@ impl Car {
     }
 
     pub fn force_from(&self, other: &Self) -> Cartessian1D<f64> {
-        // dir == true : other is in front of self
-        let r = (&self.pos).distance(&other.pos);
-        if self.pos[0] < other.pos[0] {
+        self.force_at(other.pos[0])
+    }
+
+    pub fn force_at(&self, x: f64) -> Cartessian1D<f64> {
+        // Force from a car located at x, which is in front of self if x > pos.
+        // It scales with the mass, so that heavy and light cars keep apart alike.
+        let r = (x - self.pos[0]).abs();
+        if r == 0f64 {
+            // Cars on top of each other give no direction to push in, collision detection handles them
+            return Cartessian1D::zeros();
+        }
+        if self.pos[0] < x {
             let sd = self.safe_distance(true);
             let t = sd / r;
-            Cartessian1D::new([-4.0 / r * (12.0 * t.powi(12) - 6.0 * t.powi(6))])
+            Cartessian1D::new([-4.0 * self.mass / r * (12.0 * t.powi(12) - 6.0 * t.powi(6))])
         } else {
             let sd = self.safe_distance(false);
             let t = sd / r;
-            Cartessian1D::new([4.0 / r * (12.0 * t.powi(12) - 6.0 * t.powi(6))])
+            Cartessian1D::new([4.0 * self.mass / r * (12.0 * t.powi(12) - 6.0 * t.powi(6))])
         }
     }
 }
 
 
+fn default_tolerance() -> f64 {
+    1.1
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpeedCam {
     pos: Cartessian1D<f64>,
     speed_limit: f64,
     length : f64,
     check_average : bool,
+    #[serde(default = "default_tolerance")]
+    tolerance : f64,
+    // Limits of the classes that do not follow the general one
+    #[serde(default)]
+    class_limits : BTreeMap<VehicleClass, f64>,
+    // Distance before the zone from which drivers can see the camera
+    #[serde(default)]
+    sight_distance : f64,
+    #[serde(default)]
+    log : ViolationLog,
 }
 
 impl SpeedCam {
     pub fn new(pos: Cartessian1D<f64>, speed_limit: f64, length: f64, check_average: bool) -> Self {
-        Self { pos, speed_limit, length, check_average }
+        Self { pos, speed_limit, length, check_average, tolerance : default_tolerance(), class_limits : BTreeMap::new(), sight_distance : 0f64, log : ViolationLog::new() }
+    }
+
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn with_class_limit(mut self, class: VehicleClass, speed_limit: f64) -> Self {
+        self.class_limits.insert(class, speed_limit);
+        self
+    }
+
+    pub fn with_sight_distance(mut self, sight_distance: f64) -> Self {
+        self.sight_distance = sight_distance;
+        self
+    }
+
+    pub fn sight_distance(&self) -> f64 {
+        self.sight_distance
+    }
+
+    pub fn speed_limit(&self) -> f64 {
+        self.speed_limit
+    }
+
+    pub fn limit_for(&self, class: VehicleClass) -> f64 {
+        self.class_limits.get(&class).copied().unwrap_or(self.speed_limit)
+    }
+
+    pub fn tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    pub fn measure(&mut self, car: &Car, speed: f64, time: f64, average: bool) -> bool {
+        // A ticket is issued when the measured speed exceeds the limit of the car's class times the tolerance
+        let speed_limit = self.limit_for(car.class);
+        if speed <= speed_limit * self.tolerance {
+            return false;
+        }
+        self.log.push(Violation {
+            car: car.listed_id(),
+            time,
+            pos: self.pos[0],
+            measured_speed: speed,
+            speed_limit,
+            tolerance: self.tolerance,
+            average,
+        });
+        true
+    }
+
+    pub fn violations(&self) -> &ViolationLog {
+        &self.log
     }
 
     pub fn set_max_speed(&self, car: &mut Car) {
-        car.set_max_speed(self.speed_limit);
+        car.set_max_speed(self.limit_for(car.class));
     }
 
     pub fn force_to(&self, car: &Car) -> Cartessian1D<f64> {
-        if car.vel[0] < self.speed_limit {
+        // Section control only judges the average, so drivers are not forced to brake.
+        // The braking is the same for every class, the force grows with the mass.
+        if self.check_average || car.vel[0] < self.limit_for(car.class) {
             Cartessian1D::zeros()
         } else {
-            Cartessian1D::new([- 2.0 / self.length * car.vel[0].powi(2)])
+            Cartessian1D::new([- 2.0 * car.mass / self.length * car.vel[0].powi(2)])
         }
     }
 
-    pub fn flags<'a>(&'a self) -> (SpeedCamFlag<'a>, SpeedCamFlag<'a>){
-        (SpeedCamFlag::new(&self.pos - Cartessian1D::new([self.length]), true, &self),
-        SpeedCamFlag::new(self.pos.clone(), false, &self))
+    pub fn section_speed(&self, car: &Car, elapsed: f64) -> f64 {
+        // Speed keeping the driver's average over the section at its own tolerance of the limit
+        let remaining = self.pos[0] - car.pos[0];
+        let budget = self.length / ((1.0 + car.behavior) * self.limit_for(car.class)) - elapsed;
+        if budget > 0f64 {
+            remaining / budget
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    pub fn flags(&self, id : CamId) -> (SpeedCamFlag, SpeedCamFlag){
+        // `id` is the index of the camera on its road
+        (SpeedCamFlag::new(&self.pos - Cartessian1D::new([self.length]), true, id),
+        SpeedCamFlag::new(self.pos.clone(), false, id))
     }
 }
 
 
-#[derive(Debug, Clone, Serialize)]
-pub struct SpeedCamFlag<'a> {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpeedCamFlag {
     pos: Cartessian1D<f64>,
     status : bool,
-    cam : &'a SpeedCam,
+    cam : CamId,
 }
 
-impl<'a> SpeedCamFlag<'a> {
-    pub fn new(pos : Cartessian1D<f64>, status : bool, cam : &'a SpeedCam) -> Self{
+impl SpeedCamFlag {
+    pub fn new(pos : Cartessian1D<f64>, status : bool, cam : CamId) -> Self{
         Self{ pos, status, cam }
     }
 
-    pub fn set_max_speed(&self, other : &mut Car) {
+    pub fn cam(&self) -> CamId {
+        self.cam
+    }
+
+    pub fn set_max_speed(&self, cam : &SpeedCam, other : &mut Car) {
+        // `cam` is the camera the flag belongs to, `other` is crossing the flag
+        self.apply_limit(cam, other);
+        if self.status && cam.check_average {
+            other.section_times.insert(self.cam, 0f64);
+        }
+    }
+
+    pub fn apply_limit(&self, cam : &SpeedCam, other : &mut Car) {
+        // Limit of a car behind the flag, without starting the clock of a section it did not enter
         if self.status {
-            self.cam.set_max_speed(other);
+            cam.set_max_speed(other);
         } else {
-            other.max_speed = other.own_max_speed;
+            other.max_speed = other.cruise_speed();
         }
     }
 
@@ -190,6 +942,35 @@ impl<'a> SpeedCamFlag<'a> {
 }
 
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SectionRecord {
+    pub car : CarId,
+    pub pos : f64,
+    pub speed_limit : f64,
+    #[serde(default = "default_tolerance")]
+    pub tolerance : f64,
+    pub elapsed : f64,
+    pub average_speed : f64,
+}
+
+impl SectionRecord {
+    pub fn new(cam : &SpeedCam, car : &Car, elapsed : f64) -> Self {
+        Self {
+            car : car.listed_id(),
+            pos : cam.pos[0],
+            speed_limit : cam.limit_for(car.class),
+            tolerance : cam.tolerance,
+            elapsed,
+            average_speed : cam.length / elapsed,
+        }
+    }
+
+    pub fn is_violation(&self) -> bool {
+        // Same rule as the ticket issued by the camera
+        self.average_speed > self.speed_limit * self.tolerance
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -262,35 +1043,202 @@ mod tests {
         assert!(cars[1].vel[0] < 10f64);
     }
 
+    #[test]
+    fn test_car_force_at_contact() {
+        // Two cars at the same position push each other with no force rather than an infinite one
+        let car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        assert_eq!(car.force_at(car.pos[0])[0], 0f64);
+    }
+
     #[test]
     fn test_speedcam() {
         let mut timeiter = ConstStep::<f64>::new(1e-1).unwrap();
         timeiter.set_tmax(100f64).unwrap();
         let speedcam = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 100f64, false);
-        let (open, close) = speedcam.flags();
+        let (open, close) = speedcam.flags(CamId(0));
 
         let items : Vec<TrafficItem> = vec![
             TrafficItem::Car(Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64)),
             TrafficItem::Flag(open),
             TrafficItem::Flag(close),
         ];
-        let mut trafficlist = TrafficList::new(items);
+        let mut road = Road::new(vec![speedcam], TrafficList::new(items)).unwrap();
 
-        for (_t, dt) in timeiter.into_diff() {
-            for item in trafficlist.iter_mut(){
+        for (t, dt) in timeiter.into_diff() {
+            for item in road.traffic_mut().iter_mut(){
                 match item {
                     TrafficItem::Car(c) => {
                         let force = c.drift_force();
                         let movement = c.euler(&force, dt);
                         c.renew_state(&movement);
                     },
-                    TrafficItem::Flag(_) => {},
+                    TrafficItem::Flag(_) | TrafficItem::Fixture(_) => {},
                 }
             }
 
-            for i in 1..trafficlist.len() {
-                trafficlist.check_switch(i, speedcam.speed_limit);
+            for i in 1..road.traffic().len() {
+                road.check_switch(i, t + dt);
             }
         }
+
+        assert!(road.cam(CamId(0)).violations().is_empty());
+    }
+
+    #[test]
+    fn test_car_identity() {
+        let speedcam = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 100f64, false);
+        let (open, close) = speedcam.flags(CamId(0));
+
+        let mut cars = [
+            Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64),
+            Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64),
+        ];
+        cars[1].pos[0] = 399f64;
+        let items : Vec<TrafficItem> = vec![
+            TrafficItem::Car(cars[0].clone()),
+            TrafficItem::Car(cars[1].clone()),
+            TrafficItem::Flag(open),
+            TrafficItem::Flag(close),
+        ];
+        let mut road = Road::new(vec![speedcam], TrafficList::new(items)).unwrap();
+        assert_eq!(road.traffic().position(CarId(1)), Some(1));
+
+        road.traffic_mut().car_mut(CarId(1)).unwrap().pos[0] = 401f64;
+        assert!(road.check_switch(2, 0f64));
+        assert_eq!(road.traffic().position(CarId(1)), Some(2));
+        assert_eq!(road.traffic().car(CarId(1)).unwrap().max_speed, 5f64);
+
+        let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        car.pos[0] = 450f64;
+        let id = road.insert_car(car);
+        assert_eq!(id, CarId(2));
+        assert_eq!(road.traffic().position(id), Some(3));
+        assert_eq!(road.traffic().car(id).unwrap().max_speed, 5f64);
+
+        let removed = road.traffic_mut().remove_car(CarId(0)).unwrap();
+        assert_eq!(removed.id(), Some(CarId(0)));
+        assert!(road.traffic().car(CarId(0)).is_none());
+        assert_eq!(road.traffic().position(id), Some(2));
+
+        // Two cars cannot share an identity, whether the list is built or read back
+        let twice = vec![TrafficItem::Car(removed.clone()), TrafficItem::Car(removed.clone())];
+        assert!(TrafficList::try_new(twice.clone()).is_err());
+        let json = serde_json::to_string(&TrafficList { items : twice, sections : vec![], next_id : 1 }).unwrap();
+        assert!(serde_json::from_str::<TrafficList>(&json).is_err());
+        assert!(Road::from_cars(vec![removed.clone(), removed.clone()], vec![]).is_err());
+        let mut lost = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        lost.pos[0] = f64::NAN;
+        assert!(Road::from_cars(vec![lost], vec![]).is_err());
+
+        // A removed car keeps its identity when it is put back
+        assert_eq!(road.insert_car(removed), CarId(0));
+        assert_eq!(road.insert_car(Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64)), CarId(3));
+
+        // A car put inside a section keeps to its limit, but is not timed over the part it skipped
+        let section = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 100f64, true);
+        let mut road = Road::from_cars(vec![], vec![section]).unwrap();
+        let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        car.pos[0] = 450f64;
+        let id = road.insert_car(car);
+        let car = road.traffic().car(id).unwrap();
+        assert_eq!(car.max_speed, 5f64);
+        assert!(car.section_times.is_empty());
+    }
+
+    #[test]
+    fn test_road_checkpoint() {
+        // A road owns its cameras, so it survives a trip through JSON and to another thread
+        let speedcam = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 100f64, false);
+        let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        car.pos[0] = 399f64;
+        car.vel[0] = 10f64;
+        let mut road = Road::from_cars(vec![car], vec![speedcam]).unwrap();
+        road.traffic_mut().car_mut(CarId(0)).unwrap().pos[0] = 501f64;
+        road.reorder(1f64);
+        assert_eq!(road.violations().len(), 1);
+
+        let json = serde_json::to_string(&road).unwrap();
+        let copy: Road = std::thread::spawn(move || serde_json::from_str(&json).unwrap()).join().unwrap();
+        assert_eq!(copy.violations().len(), 1);
+        assert_eq!(copy.traffic().position(CarId(0)), Some(2));
+        assert!(matches!(copy.traffic().get(1), Some(TrafficItem::Flag(f)) if f.cam() == CamId(0)));
+        assert_eq!(copy.clone().cams().len(), 1);
+
+        // Flags and departures naming a camera or fixture the road lacks are turned away
+        let flags : Vec<TrafficItem> = copy.traffic().iter().filter(|item| matches!(item, TrafficItem::Flag(_))).cloned().collect();
+        assert!(Road::new(vec![], TrafficList::new(flags)).is_err());
+        let mut value = serde_json::to_value(&road).unwrap();
+        value["cams"] = serde_json::json!([]);
+        assert!(serde_json::from_value::<Road>(value).is_err());
+        let mut value = serde_json::to_value(&road).unwrap();
+        value["departures"] = serde_json::json!([[0, 0]]);
+        assert!(serde_json::from_value::<Road>(value).is_err());
+    }
+
+    #[test]
+    fn test_road_check_back() {
+        // A car rolling back over the flags of a section leaves it and enters it again without a measurement
+        let speedcam = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 100f64, true);
+        let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        car.pos[0] = 390f64;
+        let mut road = Road::from_cars(vec![car], vec![speedcam]).unwrap();
+        let move_to = |road : &mut Road, pos : f64| {
+            road.traffic_mut().car_mut(CarId(0)).unwrap().pos[0] = pos;
+            road.reorder(1f64);
+            let positions : Vec<f64> = road.traffic().iter().map(|item| item.pos()[0]).collect();
+            assert!(positions.windows(2).all(|w| w[0] <= w[1]));
+            road.traffic().car(CarId(0)).unwrap().clone()
+        };
+
+        let inside = move_to(&mut road, 410f64);
+        assert_eq!(inside.max_speed, 5f64);
+        assert!(inside.section_times.contains_key(&CamId(0)));
+        let behind = move_to(&mut road, 395f64);
+        assert_eq!(behind.max_speed, 10f64);
+        assert!(behind.section_times.is_empty());
+        assert_eq!(road.traffic().position(CarId(0)), Some(0));
+
+        move_to(&mut road, 410f64);
+        let past = move_to(&mut road, 510f64);
+        assert_eq!(past.max_speed, 10f64);
+        let back = move_to(&mut road, 490f64);
+        assert_eq!(back.max_speed, 5f64);
+        assert!(back.section_times.is_empty());
+        assert_eq!(road.traffic().position(CarId(0)), Some(1));
+        assert_eq!(road.traffic().sections().len(), 1);
+        assert_eq!(road.violations().len(), 1);
+    }
+
+    #[test]
+    fn test_road_wrap_around() {
+        // A car crossing the end of a ring goes past the closing flag behind it before it moves to the head
+        let speedcam = SpeedCam::new(Cartessian1D::new([995f64]), 5f64, 100f64, false);
+        let mut cars = [
+            Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64),
+            Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64),
+        ];
+        cars[0].pos[0] = 500f64;
+        cars[1].pos[0] = 990f64;
+        cars[1].vel[0] = 10f64;
+        let mut road = Road::from_cars(cars.to_vec(), vec![speedcam]).unwrap();
+        assert_eq!(road.traffic().position(CarId(1)), Some(2));
+
+        road.traffic_mut().car_mut(CarId(1)).unwrap().pos[0] = 1f64;
+        road.wrap_around(&[CarId(1)], 1000f64, 1f64);
+        road.reorder(1f64);
+        assert_eq!(road.violations().len(), 1);
+        assert_eq!(road.traffic().position(CarId(1)), Some(0));
+        assert_eq!(road.traffic().car(CarId(1)).unwrap().pos[0], 1f64);
+        let positions : Vec<f64> = road.traffic().iter().map(|item| item.pos()[0]).collect();
+        assert!(positions.windows(2).all(|w| w[0] <= w[1]));
+
+        // Rolling back over the start puts the car at the tail without a second passage of the flags
+        road.traffic_mut().car_mut(CarId(1)).unwrap().pos[0] = 999f64;
+        road.wrap_backward(&[CarId(1)]);
+        road.reorder(2f64);
+        assert_eq!(road.violations().len(), 1);
+        assert_eq!(road.traffic().position(CarId(1)), Some(road.traffic().len() - 1));
+        let positions : Vec<f64> = road.traffic().iter().map(|item| item.pos()[0]).collect();
+        assert!(positions.windows(2).all(|w| w[0] <= w[1]));
     }
 }
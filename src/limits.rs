@@ -0,0 +1,79 @@
+use crate::CarId;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    pub max_acceleration: f64,
+    // Braking harder than this is an emergency, which cannot go beyond the emergency deceleration
+    pub comfortable_deceleration: f64,
+    pub emergency_deceleration: f64,
+}
+
+impl Limits {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("max_acceleration", self.max_acceleration),
+            ("comfortable_deceleration", self.comfortable_deceleration),
+            ("emergency_deceleration", self.emergency_deceleration),
+        ] {
+            if value.is_nan() || value <= 0f64 {
+                return Err(format!("{} {} should be positive", name, value));
+            }
+        }
+        if self.comfortable_deceleration > self.emergency_deceleration {
+            return Err(format!(
+                "comfortable deceleration {} exceeds the emergency one {}",
+                self.comfortable_deceleration, self.emergency_deceleration
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, speed: f64, demanded: f64, dt: f64) -> (f64, bool) {
+        // Acceleration actually achieved over dt, and whether it takes emergency braking.
+        // Braking brings the car to rest rather than driving it backwards.
+        let stop = speed.max(0f64) / dt;
+        if demanded.is_nan() {
+            // Forces that blew up are met with full braking
+            return (-self.emergency_deceleration.min(stop), true);
+        }
+        let a = demanded.max(-stop);
+        (a.clamp(-self.emergency_deceleration, self.max_acceleration), a < -self.comfortable_deceleration)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmergencyBraking {
+    pub car: CarId,
+    pub time: f64,
+    pub pos: f64,
+    pub speed: f64,
+    pub demanded: f64,
+    pub applied: f64,
+}
+
+pub fn write_csv<W: Write>(brakings: &[EmergencyBraking], mut writer: W) -> std::io::Result<()> {
+    writeln!(writer, "car,time,pos,speed,demanded,applied")?;
+    for b in brakings {
+        writeln!(writer, "{},{},{},{},{},{}", b.car, b.time, b.pos, b.speed, b.demanded, b.applied)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits { max_acceleration: 2f64, comfortable_deceleration: 3f64, emergency_deceleration: 8f64 };
+        assert_eq!(limits.apply(10f64, 5f64, 0.1), (2f64, false));
+        assert_eq!(limits.apply(10f64, -2f64, 0.1), (-2f64, false));
+        assert_eq!(limits.apply(10f64, -1e9, 0.1), (-8f64, true));
+        assert_eq!(limits.apply(10f64, f64::NAN, 0.1), (-8f64, true));
+        // A car about to stop only brakes down to rest
+        assert_eq!(limits.apply(0.2, -5f64, 0.1), (-2f64, false));
+        assert!(Limits { comfortable_deceleration: 9f64, ..limits }.validate().is_err());
+    }
+}
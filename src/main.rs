@@ -1,3 +1,277 @@
+use clap::{Arg, ArgMatches, Command};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter};
+use std::path::Path;
+use traffic::fundamental::{self, Sweep};
+use traffic::collision;
+use traffic::inflow;
+use traffic::limits;
+use traffic::{CarId, Recorder, Sample, Scenario};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Summary {
+    cars: usize,
+    samples: usize,
+    time: f64,
+    mean_speed: f64,
+    min_speed: f64,
+    max_speed: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    violations: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mean_travel_time: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    missed_exits: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    emergency_brakings: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crashes: Option<usize>,
+}
+
+impl Summary {
+    fn new() -> Self {
+        Self {
+            min_speed: f64::INFINITY,
+            max_speed: f64::NEG_INFINITY,
+            ..Default::default()
+        }
+    }
+
+    fn add(&mut self, time: f64, speed: f64) {
+        self.mean_speed += (speed - self.mean_speed) / (self.samples + 1) as f64;
+        self.min_speed = self.min_speed.min(speed);
+        self.max_speed = self.max_speed.max(speed);
+        self.time = self.time.max(time);
+        self.samples += 1;
+    }
+
+    fn from_samples(samples: &[Sample]) -> Self {
+        let mut summary = Self::new();
+        let mut cars = BTreeSet::new();
+        for s in samples {
+            cars.insert(s.car);
+            summary.add(s.time, s.vel);
+        }
+        summary.cars = cars.len();
+        summary
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut scenario = Scenario::load(matches.value_of("scenario").unwrap())?;
+    // A fresh seed is drawn when the scenario has none, the header keeps it for reruns.
+    // It stays within the range of toml integers so that it can be written back to the scenario.
+    let seed = *scenario.seed.get_or_insert_with(|| rand::random::<u64>() >> 1);
+    // Mixes of drivers are placed from the seed, so they are checked again with the new one
+    scenario.validate()?;
+    let interval: f64 = matches.value_of_t("interval")?;
+    if interval.is_nan() || interval < 0f64 {
+        return Err("sampling interval should be non-negative".into());
+    }
+    let output = Path::new(matches.value_of("output").unwrap());
+    std::fs::create_dir_all(output)?;
+
+    let mut recorder = Recorder::new(interval).with_header(serde_json::to_string(&scenario)?);
+    let mut mean_travel_time = None;
+    let mut missed_exits = None;
+    let mut emergency_brakings = None;
+    let mut crashes = None;
+    let mut abort = None;
+    let violations = if matches.is_present("cellular") {
+        let mut road = scenario.cellular_road()?;
+        road.run_recorded(scenario.integrator.tmax, &mut recorder);
+        road.violations().clone()
+    } else {
+        let mut sim = scenario.simulation()?;
+        sim.run_observed(scenario.integrator.tmax, &mut recorder);
+        if !sim.is_periodic() {
+            inflow::write_csv(sim.exits(), BufWriter::new(File::create(output.join("exits.csv"))?))?;
+            let times: Vec<f64> = sim.exits().iter().filter_map(|e| e.travel_time()).collect();
+            if !times.is_empty() {
+                mean_travel_time = Some(times.iter().sum::<f64>() / times.len() as f64);
+            }
+            missed_exits = Some(sim.missed_exits());
+        }
+        limits::write_csv(sim.emergency_brakings(), BufWriter::new(File::create(output.join("brakings.csv"))?))?;
+        emergency_brakings = Some(sim.emergency_brakings().len());
+        collision::write_csv(sim.crashes(), BufWriter::new(File::create(output.join("crashes.csv"))?))?;
+        crashes = Some(sim.crashes().len());
+        if sim.is_aborted() {
+            abort = sim.crashes().last().cloned();
+        }
+        sim.violations()
+    };
+
+    match matches.value_of("format").unwrap() {
+        "binary" => recorder.write_binary(BufWriter::new(File::create(output.join("trajectory.bin"))?))?,
+        _ => recorder.write_csv(BufWriter::new(File::create(output.join("trajectory.csv"))?))?,
+    }
+
+    violations.write_csv(BufWriter::new(File::create(output.join("violations.csv"))?))?;
+
+    let mut summary = Summary::from_samples(recorder.samples());
+    summary.violations = Some(violations.len());
+    summary.seed = Some(seed);
+    summary.mean_travel_time = mean_travel_time;
+    summary.missed_exits = missed_exits;
+    summary.emergency_brakings = emergency_brakings;
+    summary.crashes = crashes;
+
+    serde_json::to_writer_pretty(File::create(output.join("summary.json"))?, &summary)?;
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+    // Outputs up to the crash are kept for the diagnosis
+    match abort {
+        Some(crash) => Err(format!("run aborted : {}", crash).into()),
+        None => Ok(()),
+    }
+}
+
+fn validate(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let path = matches.value_of("scenario").unwrap();
+    let scenario = Scenario::load(path)?;
+    // The run is built as `run` would, so that a scenario passing here does not fail there
+    let sim = scenario.simulation()?;
+    println!(
+        "{} : {} cars, {} cameras on a road of length {} with {} lanes",
+        path,
+        sim.cars().count(),
+        scenario.cameras.len(),
+        scenario.road.length,
+        scenario.road.lanes
+    );
+    Ok(())
+}
+
+fn read_csv(path: &Path) -> Result<Vec<Sample>, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut samples = vec![];
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.starts_with("time") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() != 6 {
+            return Err(format!("malformed trajectory line : {}", line).into());
+        }
+        samples.push(Sample {
+            time: fields[0].parse()?,
+            car: CarId(fields[1].parse()?),
+            pos: fields[2].parse()?,
+            vel: fields[3].parse()?,
+            lane: fields[4].parse()?,
+            max_speed: fields[5].parse()?,
+        });
+    }
+    Ok(samples)
+}
+
+fn summarize(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(matches.value_of("trajectory").unwrap());
+    let samples = match path.extension().and_then(|e| e.to_str()) {
+        Some("bin") => Recorder::read_binary(BufReader::new(File::open(path)?))?.1,
+        _ => read_csv(path)?,
+    };
+
+    println!("{}", serde_json::to_string_pretty(&Summary::from_samples(&samples))?);
+    Ok(())
+}
+
+fn sweep(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let sweep = Sweep::load(matches.value_of("sweep").unwrap())?;
+    let output = Path::new(matches.value_of("output").unwrap());
+    std::fs::create_dir_all(output)?;
+
+    let points = sweep.run()?;
+    fundamental::write_csv(&points, BufWriter::new(File::create(output.join("fundamental.csv"))?))?;
+    for p in points.iter() {
+        println!(
+            "density {:.4} : flow {:.4} +- {:.4}, speed {:.4} +- {:.4}",
+            p.density, p.flow, p.flow_err, p.speed, p.speed_err
+        );
+    }
+    Ok(())
+}
+
 fn main() {
-    println!("Hello, world!");
+    let matches = Command::new("traffic")
+        .about("Traffic simulation with speed cameras")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(
+            Command::new("run")
+                .about("Run a scenario and write its trajectory, violations and summary")
+                .arg(Arg::new("scenario").required(true).help("Scenario file"))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help("Output directory"),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .default_value("1.0")
+                        .help("Time between trajectory samples"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(["csv", "binary"])
+                        .default_value("csv")
+                        .help("Trajectory file format"),
+                )
+                .arg(
+                    Arg::new("cellular")
+                        .long("cellular")
+                        .help("Run the scenario on the Nagel-Schreckenberg cellular automaton"),
+                ),
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("Check a scenario file without running it")
+                .arg(Arg::new("scenario").required(true).help("Scenario file")),
+        )
+        .subcommand(
+            Command::new("summarize")
+                .about("Summary statistics of a trajectory file")
+                .arg(Arg::new("trajectory").required(true).help("Trajectory csv or bin file written by run")),
+        )
+        .subcommand(
+            Command::new("sweep")
+                .about("Flow-density curve of a ring road over a range of densities")
+                .arg(Arg::new("sweep").required(true).help("Sweep file"))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help("Output directory"),
+                ),
+        )
+        .get_matches();
+
+    let result = match matches.subcommand() {
+        Some(("run", m)) => run(m),
+        Some(("validate", m)) => validate(m),
+        Some(("summarize", m)) => summarize(m),
+        Some(("sweep", m)) => sweep(m),
+        _ => unreachable!(),
+    };
+
+    if let Err(e) = result {
+        eprintln!("error : {}", e);
+        std::process::exit(1);
+    }
 }
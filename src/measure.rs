@@ -0,0 +1,328 @@
+use crate::recorder::Observer;
+use crate::{Car, CarId, Simulation, TrafficList};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub start: f64,
+    pub end: f64,
+    pub lane: Option<usize>,
+}
+
+impl Segment {
+    pub fn new(start: f64, end: f64) -> Self {
+        if start.is_nan() || end.is_nan() || start >= end {
+            panic!("Invalid segment : start {} should be smaller than end {}", start, end);
+        }
+        Self { start, end, lane: None }
+    }
+
+    pub fn with_lane(mut self, lane: usize) -> Self {
+        self.lane = Some(lane);
+        self
+    }
+
+    pub fn length(&self) -> f64 {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, car: &Car) -> bool {
+        (self.start..self.end).contains(&car.pos[0]) && !matches!(self.lane, Some(l) if l != car.lane)
+    }
+
+    pub fn density(&self, traffic: &TrafficList) -> f64 {
+        traffic.cars().filter(|c| self.contains(c)).count() as f64 / self.length()
+    }
+
+    pub fn flow(&self, traffic: &TrafficList) -> f64 {
+        traffic.cars().filter(|c| self.contains(c)).map(|c| c.vel[0]).sum::<f64>() / self.length()
+    }
+
+    pub fn space_mean_speed(&self, traffic: &TrafficList) -> Option<f64> {
+        let (n, sum) = traffic
+            .cars()
+            .filter(|c| self.contains(c))
+            .fold((0usize, 0f64), |(n, sum), c| (n + 1, sum + c.vel[0]));
+        if n == 0 {
+            None
+        } else {
+            Some(sum / n as f64)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    pub start: f64,
+    pub end: f64,
+    pub density: f64,
+    pub flow: f64,
+    pub space_mean_speed: Option<f64>,
+    pub time_mean_speed: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SegmentMonitor {
+    segment: Segment,
+    window: f64,
+    window_start: f64,
+    last_time: Option<f64>,
+    occupancy: f64,
+    distance: f64,
+    measurements: Vec<Measurement>,
+}
+
+impl SegmentMonitor {
+    // Edie's definitions : density is the total time spent and flow the total distance
+    // travelled in the segment, both divided by the area of the space-time window.
+    pub fn new(segment: Segment, window: f64) -> Self {
+        if window.is_nan() || window <= 0f64 {
+            panic!("Invalid time window : {} should be positive", window);
+        }
+        Self {
+            segment,
+            window,
+            window_start: 0f64,
+            last_time: None,
+            occupancy: 0f64,
+            distance: 0f64,
+            measurements: vec![],
+        }
+    }
+
+    pub fn segment(&self) -> &Segment {
+        &self.segment
+    }
+
+    pub fn measurements(&self) -> &[Measurement] {
+        &self.measurements
+    }
+}
+
+impl Observer for SegmentMonitor {
+    fn observe(&mut self, sim: &Simulation) {
+        let time = sim.time();
+        let dt = match self.last_time.replace(time) {
+            Some(last) => time - last,
+            None => {
+                self.window_start = time;
+                return;
+            }
+        };
+
+        for c in sim.cars().filter(|c| self.segment.contains(c)) {
+            self.occupancy += dt;
+            self.distance += c.vel[0] * dt;
+        }
+
+        if time + 0.5 * sim.dt() >= self.window_start + self.window {
+            let area = self.segment.length() * (time - self.window_start);
+            self.measurements.push(Measurement {
+                start: self.window_start,
+                end: time,
+                density: self.occupancy / area,
+                flow: self.distance / area,
+                space_mean_speed: (self.occupancy > 0f64).then(|| self.distance / self.occupancy),
+                time_mean_speed: None,
+            });
+            self.window_start = time;
+            self.occupancy = 0f64;
+            self.distance = 0f64;
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Passage {
+    pub time: f64,
+    pub car: CarId,
+    pub lane: usize,
+    pub speed: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoopDetector {
+    pos: f64,
+    lane: Option<usize>,
+    window: f64,
+    window_start: Option<f64>,
+    last_pos: HashMap<CarId, f64>,
+    passages: Vec<Passage>,
+    measurements: Vec<Measurement>,
+}
+
+impl LoopDetector {
+    pub fn new(pos: f64, window: f64) -> Self {
+        if window.is_nan() || window <= 0f64 {
+            panic!("Invalid time window : {} should be positive", window);
+        }
+        Self {
+            pos,
+            lane: None,
+            window,
+            window_start: None,
+            last_pos: HashMap::new(),
+            passages: vec![],
+            measurements: vec![],
+        }
+    }
+
+    pub fn with_lane(mut self, lane: usize) -> Self {
+        self.lane = Some(lane);
+        self
+    }
+
+    pub fn pos(&self) -> f64 {
+        self.pos
+    }
+
+    pub fn passages(&self) -> &[Passage] {
+        &self.passages
+    }
+
+    pub fn take_passages(&mut self) -> Vec<Passage> {
+        // Passages so far are handed over and left out of the windows still open
+        std::mem::take(&mut self.passages)
+    }
+
+    pub fn measurements(&self) -> &[Measurement] {
+        &self.measurements
+    }
+
+    fn crossed(&self, from: f64, to: f64, ring: Option<f64>) -> bool {
+        match ring {
+            // Only the periodic boundary takes a car back by more than half the ring, a car rolling back is no passage
+            Some(length) if from - to > 0.5 * length => from < self.pos || self.pos <= to,
+            _ => from < self.pos && self.pos <= to,
+        }
+    }
+
+    fn aggregate(&self, start: f64, end: f64) -> Measurement {
+        // Spot speeds give the time mean, their harmonic mean estimates the space mean
+        let speeds: Vec<f64> = self
+            .passages
+            .iter()
+            .filter(|p| start < p.time && p.time <= end)
+            .map(|p| p.speed)
+            .collect();
+        let n = speeds.len() as f64;
+        let flow = n / (end - start);
+        let time_mean_speed = (n > 0f64).then(|| speeds.iter().sum::<f64>() / n);
+        let space_mean_speed = (n > 0f64).then(|| n / speeds.iter().map(|v| 1f64 / v).sum::<f64>());
+
+        Measurement {
+            start,
+            end,
+            density: space_mean_speed.map_or(0f64, |v| flow / v),
+            flow,
+            space_mean_speed,
+            time_mean_speed,
+        }
+    }
+}
+
+impl Observer for LoopDetector {
+    fn observe(&mut self, sim: &Simulation) {
+        let time = sim.time();
+        let start = *self.window_start.get_or_insert(time);
+        let ring = sim.is_periodic().then(|| sim.length());
+
+        // Cars that left the road are forgotten, only those still on it are kept for the next step
+        let mut last_pos = HashMap::with_capacity(self.last_pos.len());
+        for c in sim.cars() {
+            let pos = c.pos[0];
+            last_pos.insert(c.listed_id(), pos);
+            if let Some(&last) = self.last_pos.get(&c.listed_id()) {
+                if !matches!(self.lane, Some(l) if l != c.lane) && self.crossed(last, pos, ring) {
+                    self.passages.push(Passage {
+                        time,
+                        car: c.listed_id(),
+                        lane: c.lane,
+                        speed: c.vel[0],
+                    });
+                }
+            }
+        }
+        self.last_pos = last_pos;
+
+        if time + 0.5 * sim.dt() >= start + self.window {
+            self.measurements.push(self.aggregate(start, time));
+            self.window_start = Some(time);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inflow::{Arrivals, Inflow, SpeedDistribution};
+    use crate::{Car, Road, TrafficItem};
+    use approx::assert_abs_diff_eq;
+
+    fn ring(n: usize, length: f64, speed: f64) -> Simulation {
+        let items = (0..n)
+            .map(|k| {
+                let mut car = Car::new(k % 2, 1f64, speed, 1f64, 0f64, speed);
+                car.pos[0] = length * k as f64 / n as f64;
+                car.vel[0] = speed;
+                TrafficItem::Car(car)
+            })
+            .collect();
+        Simulation::new(Road::try_from(TrafficList::new(items)).unwrap(), length, 1e-1).with_lanes(2)
+    }
+
+    #[test]
+    fn test_segment() {
+        let sim = ring(10, 1000f64, 5f64);
+        let segment = Segment::new(0f64, 500f64);
+        assert_abs_diff_eq!(segment.density(sim.traffic()), 0.01, epsilon = 1e-12);
+        assert_abs_diff_eq!(segment.flow(sim.traffic()), 0.05, epsilon = 1e-12);
+        assert_abs_diff_eq!(segment.space_mean_speed(sim.traffic()).unwrap(), 5f64, epsilon = 1e-12);
+
+        let segment = segment.with_lane(1);
+        assert_abs_diff_eq!(segment.density(sim.traffic()), 0.004, epsilon = 1e-12);
+        assert!(Segment::new(950f64, 990f64).space_mean_speed(sim.traffic()).is_none());
+    }
+
+    #[test]
+    fn test_monitor_and_detector() {
+        let mut sim = ring(10, 1000f64, 5f64);
+        let monitor = SegmentMonitor::new(Segment::new(0f64, 1000f64), 100f64);
+        let detector = LoopDetector::new(250f64, 100f64);
+        let mut observers = (monitor, detector);
+        sim.run_observed(400f64, &mut observers);
+        let (monitor, detector) = observers;
+
+        // Ten cars at speed 5 on a ring of 1000 : density 0.01 and flow 0.05
+        assert_eq!(monitor.measurements().len(), 4);
+        for m in monitor.measurements() {
+            assert_abs_diff_eq!(m.density, 0.01, epsilon = 1e-9);
+            assert_abs_diff_eq!(m.flow, 0.05, epsilon = 1e-6);
+        }
+
+        assert_eq!(detector.measurements().len(), 4);
+        assert_eq!(detector.passages().len(), 20);
+        for m in detector.measurements() {
+            assert_abs_diff_eq!(m.flow, 0.05, epsilon = 1e-9);
+            assert_abs_diff_eq!(m.time_mean_speed.unwrap(), 5f64, epsilon = 1e-6);
+            assert_abs_diff_eq!(m.density, 0.01, epsilon = 1e-6);
+        }
+
+        // On an open road the cars that left are dropped from the positions kept
+        let template = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        let inflow = Inflow::new(Arrivals::FixedHeadway { headway: 5f64 }, SpeedDistribution::Fixed { speed: 10f64 }, template);
+        let mut sim = Simulation::new(Road::try_from(TrafficList::new(vec![])).unwrap(), 500f64, 1e-1).with_open_boundary().with_inflow(inflow);
+        let mut detector = LoopDetector::new(250f64, 100f64);
+        sim.run_observed(300f64, &mut detector);
+        assert!(!sim.exits().is_empty());
+        assert!(detector.passages().len() > 40);
+        assert_eq!(detector.last_pos.len(), sim.cars().count());
+
+        let detector = LoopDetector::new(5f64, 100f64);
+        assert!(detector.crossed(990f64, 10f64, Some(1000f64)));
+        assert!(!detector.crossed(990f64, 10f64, None));
+        assert!(!detector.crossed(6f64, 4f64, Some(1000f64)));
+        assert!(!detector.crossed(6f64, 4f64, None));
+    }
+}
@@ -0,0 +1,363 @@
+use crate::Car;
+use serde::{Deserialize, Serialize};
+
+pub trait CarFollowingModel {
+    // Acceleration of `car` on an empty road
+    fn free_acceleration(&self, car: &Car) -> f64;
+
+    // Acceleration of `car` behind `leader`, whose position is `distance` ahead
+    fn acceleration(&self, car: &Car, leader: &Car, distance: f64) -> f64;
+
+    // Extra acceleration from a `follower` at `distance` behind.
+    // Only force based models push the car forward, the others ignore it.
+    fn push(&self, _car: &Car, _follower: &Car, _distance: f64) -> f64 {
+        0f64
+    }
+}
+
+pub fn model_of<'m>(car: &'m Car, default: &'m dyn CarFollowingModel) -> &'m dyn CarFollowingModel {
+    // A car's own model takes precedence over the one of the simulation
+    match &car.model {
+        Some(model) => model,
+        None => default,
+    }
+}
+
+fn gap(car: &Car, leader: &Car, distance: f64) -> f64 {
+    // Bumper to bumper distance, positions being the centers of the cars
+    distance - 0.5 * (car.size + leader.size)
+}
+
+fn check(name: &str, value: f64) -> Result<(), String> {
+    if value > 0f64 {
+        Ok(())
+    } else {
+        Err(format!("{} {} should be positive", name, value))
+    }
+}
+
+// Repulsion from `Car::force_from` on top of the tanh relaxation of `Car::drift_force`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LennardJones;
+
+impl CarFollowingModel for LennardJones {
+    fn free_acceleration(&self, car: &Car) -> f64 {
+        car.drift_force()[0] / car.mass
+    }
+
+    fn acceleration(&self, car: &Car, _leader: &Car, distance: f64) -> f64 {
+        (car.drift_force()[0] + car.force_at(car.pos[0] + distance)[0]) / car.mass
+    }
+
+    fn push(&self, car: &Car, _follower: &Car, distance: f64) -> f64 {
+        car.force_at(car.pos[0] - distance)[0] / car.mass
+    }
+}
+
+// Intelligent Driver Model (Treiber, Hennecke and Helbing, 2000)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Idm {
+    pub acceleration: f64,
+    pub deceleration: f64,
+    pub time_headway: f64,
+    pub min_gap: f64,
+    pub delta: f64,
+}
+
+impl Default for Idm {
+    fn default() -> Self {
+        Self {
+            acceleration: 1.0,
+            deceleration: 1.5,
+            time_headway: 1.5,
+            min_gap: 2.0,
+            delta: 4.0,
+        }
+    }
+}
+
+impl CarFollowingModel for Idm {
+    fn free_acceleration(&self, car: &Car) -> f64 {
+        // A car held to a zero limit, like the standing car of a stop line, brakes down to rest
+        if car.max_speed <= 0f64 {
+            return if car.vel[0] > 0f64 { -self.deceleration } else { 0f64 };
+        }
+        self.acceleration * (1f64 - (car.vel[0] / car.max_speed).powf(self.delta))
+    }
+
+    fn acceleration(&self, car: &Car, leader: &Car, distance: f64) -> f64 {
+        let v = car.vel[0];
+        let approach = v - leader.vel[0];
+        let desired = self.min_gap
+            + (v * self.time_headway + v * approach / (2f64 * (self.acceleration * self.deceleration).sqrt())).max(0f64);
+        let s = gap(car, leader, distance).max(1e-3);
+        self.free_acceleration(car) - self.acceleration * (desired / s).powi(2)
+    }
+}
+
+// Optimal Velocity Model (Bando et al., 1995), the optimal velocity reaching max_speed on a free road
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ovm {
+    pub sensitivity: f64,
+    pub safety_gap: f64,
+    pub width: f64,
+}
+
+impl Default for Ovm {
+    fn default() -> Self {
+        Self {
+            sensitivity: 1.0,
+            safety_gap: 10.0,
+            width: 5.0,
+        }
+    }
+}
+
+impl Ovm {
+    pub fn optimal_velocity(&self, v0: f64, s: f64) -> f64 {
+        let offset = (self.safety_gap / self.width).tanh();
+        v0 * (((s - self.safety_gap) / self.width).tanh() + offset) / (1f64 + offset)
+    }
+}
+
+impl CarFollowingModel for Ovm {
+    fn free_acceleration(&self, car: &Car) -> f64 {
+        self.sensitivity * (car.max_speed - car.vel[0])
+    }
+
+    fn acceleration(&self, car: &Car, leader: &Car, distance: f64) -> f64 {
+        let s = gap(car, leader, distance).max(0f64);
+        self.sensitivity * (self.optimal_velocity(car.max_speed, s) - car.vel[0])
+    }
+}
+
+// Gipps (1981), the speed after one reaction time turned into an acceleration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Gipps {
+    pub acceleration: f64,
+    pub deceleration: f64,
+    pub leader_deceleration: f64,
+    pub reaction_time: f64,
+    pub min_gap: f64,
+}
+
+impl Default for Gipps {
+    fn default() -> Self {
+        Self {
+            acceleration: 1.7,
+            deceleration: 3.0,
+            leader_deceleration: 3.5,
+            reaction_time: 1.0,
+            min_gap: 1.0,
+        }
+    }
+}
+
+impl Gipps {
+    fn free_speed(&self, car: &Car) -> f64 {
+        let (v, v0, tau) = (car.vel[0], car.max_speed, self.reaction_time);
+        if v0 <= 0f64 {
+            return 0f64;
+        }
+        v + 2.5 * self.acceleration * tau * (1f64 - v / v0) * (0.025 + v / v0).max(0f64).sqrt()
+    }
+}
+
+impl CarFollowingModel for Gipps {
+    fn free_acceleration(&self, car: &Car) -> f64 {
+        (self.free_speed(car).max(0f64) - car.vel[0]) / self.reaction_time
+    }
+
+    fn acceleration(&self, car: &Car, leader: &Car, distance: f64) -> f64 {
+        let (v, vl, b, tau) = (car.vel[0], leader.vel[0], self.deceleration, self.reaction_time);
+        let s = gap(car, leader, distance) - self.min_gap;
+        let radicand = (b * tau).powi(2) + b * (2f64 * s - v * tau + vl * vl / self.leader_deceleration);
+        let safe = if radicand > 0f64 { -b * tau + radicand.sqrt() } else { 0f64 };
+        (self.free_speed(car).min(safe).max(0f64) - v) / tau
+    }
+}
+
+// Krauss (1998) without the random dawdling. The safe speed holds for updates made
+// within one reaction time, so the speed relaxes to it over a shorter time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Krauss {
+    pub acceleration: f64,
+    pub deceleration: f64,
+    pub reaction_time: f64,
+    pub relaxation: f64,
+    pub min_gap: f64,
+}
+
+impl Default for Krauss {
+    fn default() -> Self {
+        Self {
+            acceleration: 2.6,
+            deceleration: 4.5,
+            reaction_time: 1.0,
+            relaxation: 0.5,
+            min_gap: 2.5,
+        }
+    }
+}
+
+impl CarFollowingModel for Krauss {
+    fn free_acceleration(&self, car: &Car) -> f64 {
+        let v = car.vel[0];
+        let target = car.max_speed.min(v + self.acceleration * self.relaxation);
+        (target - v) / self.relaxation
+    }
+
+    fn acceleration(&self, car: &Car, leader: &Car, distance: f64) -> f64 {
+        let (v, vl, tau) = (car.vel[0], leader.vel[0], self.reaction_time);
+        let s = gap(car, leader, distance) - self.min_gap;
+        let safe = vl + (s - vl * tau) / (0.5 * (v + vl) / self.deceleration + tau);
+        let target = car.max_speed.min(v + self.acceleration * self.relaxation).min(safe).max(0f64);
+        (target - v) / self.relaxation
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Model {
+    #[default]
+    LennardJones,
+    Idm(Idm),
+    Ovm(Ovm),
+    Gipps(Gipps),
+    Krauss(Krauss),
+}
+
+impl Model {
+    fn inner(&self) -> &dyn CarFollowingModel {
+        match self {
+            Model::LennardJones => &LennardJones,
+            Model::Idm(m) => m,
+            Model::Ovm(m) => m,
+            Model::Gipps(m) => m,
+            Model::Krauss(m) => m,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Model::LennardJones => Ok(()),
+            Model::Idm(m) => {
+                check("acceleration", m.acceleration)?;
+                check("deceleration", m.deceleration)?;
+                check("time_headway", m.time_headway)?;
+                check("min_gap", m.min_gap)?;
+                check("delta", m.delta)
+            }
+            Model::Ovm(m) => {
+                check("sensitivity", m.sensitivity)?;
+                check("safety_gap", m.safety_gap)?;
+                check("width", m.width)
+            }
+            Model::Gipps(m) => {
+                check("acceleration", m.acceleration)?;
+                check("deceleration", m.deceleration)?;
+                check("leader_deceleration", m.leader_deceleration)?;
+                check("reaction_time", m.reaction_time)?;
+                check("min_gap", m.min_gap)
+            }
+            Model::Krauss(m) => {
+                check("acceleration", m.acceleration)?;
+                check("deceleration", m.deceleration)?;
+                check("reaction_time", m.reaction_time)?;
+                check("relaxation", m.relaxation)?;
+                check("min_gap", m.min_gap)
+            }
+        }
+    }
+}
+
+impl CarFollowingModel for Model {
+    fn free_acceleration(&self, car: &Car) -> f64 {
+        self.inner().free_acceleration(car)
+    }
+
+    fn acceleration(&self, car: &Car, leader: &Car, distance: f64) -> f64 {
+        self.inner().acceleration(car, leader, distance)
+    }
+
+    fn push(&self, car: &Car, follower: &Car, distance: f64) -> f64 {
+        self.inner().push(car, follower, distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    fn models() -> Vec<Model> {
+        vec![
+            Model::LennardJones,
+            Model::Idm(Idm::default()),
+            Model::Ovm(Ovm::default()),
+            Model::Gipps(Gipps::default()),
+            Model::Krauss(Krauss::default()),
+        ]
+    }
+
+    #[test]
+    fn test_free_road() {
+        for model in models() {
+            let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+            for _ in 0..1000 {
+                car.vel[0] += model.free_acceleration(&car) * 1e-1;
+            }
+            assert_abs_diff_eq!(car.vel[0], 10f64, epsilon = 0.1);
+        }
+    }
+
+    #[test]
+    fn test_zero_limit() {
+        // A car held to a zero limit never gets a NaN, it brakes if moving and stays put otherwise
+        let leader = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        for model in models() {
+            let mut car = Car::new(0, 1f64, 0f64, 1f64, 0f64, 10f64);
+            for v in [0f64, 5f64] {
+                car.vel[0] = v;
+                let (free, following) = (model.free_acceleration(&car), model.acceleration(&car, &leader, 50f64));
+                assert!(free.is_finite() && free <= 0f64, "{:?} gave {} at {}", model, free, v);
+                assert!(following.is_finite(), "{:?} gave {} at {}", model, following, v);
+            }
+            assert!(model.validate().is_ok());
+        }
+        assert!(Model::Gipps(Gipps { min_gap: -1f64, ..Gipps::default() }).validate().is_err());
+        assert!(Model::Krauss(Krauss { min_gap: f64::NAN, ..Krauss::default() }).validate().is_err());
+    }
+
+    #[test]
+    fn test_stop_behind_leader() {
+        // Every model but the OVM, which is known not to be collision free,
+        // brings a car at full speed to rest behind a standing car without touching it
+        for model in models().into_iter().filter(|m| !matches!(m, Model::Ovm(_))) {
+            let leader = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+            let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+            car.vel[0] = 10f64;
+            let mut distance = 200f64;
+            for _ in 0..3000 {
+                car.vel[0] += model.acceleration(&car, &leader, distance) * 1e-2;
+                distance -= car.vel[0] * 1e-2;
+            }
+            assert!(gap(&car, &leader, distance) > 0f64, "{:?} crashed", model);
+            assert_abs_diff_eq!(car.vel[0], 0f64, epsilon = 0.1);
+        }
+
+        // The OVM relaxes to the optimal velocity of the gap instead
+        let ovm = Ovm::default();
+        let mut leader = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        leader.vel[0] = 3f64;
+        let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        for _ in 0..1000 {
+            car.vel[0] += ovm.acceleration(&car, &leader, 11f64) * 1e-1;
+        }
+        assert_abs_diff_eq!(car.vel[0], ovm.optimal_velocity(10f64, 10f64), epsilon = 1e-6);
+    }
+}
@@ -0,0 +1,83 @@
+use rand::Rng;
+use rand_distr::StandardNormal;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Noise {
+    // Acceleration noise of standard deviation sigma, correlated over tau
+    OrnsteinUhlenbeck { sigma: f64, tau: f64 },
+    // Braking events happen at the given rate per unit time and last for duration
+    RandomBraking { rate: f64, deceleration: f64, duration: f64 },
+}
+
+impl Noise {
+    pub fn validate(&self) -> Result<(), String> {
+        let (name, value) = match *self {
+            Noise::OrnsteinUhlenbeck { sigma, .. } if sigma.is_nan() || sigma < 0f64 => ("sigma", sigma),
+            Noise::OrnsteinUhlenbeck { tau, .. } if tau.is_nan() || tau <= 0f64 => ("tau", tau),
+            Noise::RandomBraking { rate, .. } if rate.is_nan() || rate < 0f64 => ("rate", rate),
+            Noise::RandomBraking { deceleration, .. } if deceleration.is_nan() || deceleration < 0f64 => ("deceleration", deceleration),
+            Noise::RandomBraking { duration, .. } if duration.is_nan() || duration <= 0f64 => ("duration", duration),
+            _ => return Ok(()),
+        };
+        Err(format!("{} {} is out of range", name, value))
+    }
+
+    pub fn acceleration<R: Rng + ?Sized>(&self, state: &mut f64, speed: f64, dt: f64, rng: &mut R) -> f64 {
+        // `state` is the current noise for the OU process and the remaining braking time otherwise
+        match *self {
+            Noise::OrnsteinUhlenbeck { sigma, tau } => {
+                // Exact update, so the stationary deviation is sigma whatever dt is
+                let decay = (-dt / tau).exp();
+                let xi: f64 = rng.sample(StandardNormal);
+                *state = *state * decay + sigma * (1f64 - decay * decay).sqrt() * xi;
+                *state
+            }
+            Noise::RandomBraking { rate, deceleration, duration } => {
+                if *state <= 0f64 && rng.gen::<f64>() < 1f64 - (-rate * dt).exp() {
+                    *state = duration;
+                }
+                if *state > 0f64 {
+                    *state -= dt;
+                    // Braking brings the car to rest at the most, it never backs up
+                    -deceleration.min(speed.max(0f64) / dt)
+                } else {
+                    0f64
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use rand::SeedableRng;
+    use rand_pcg::Pcg64;
+
+    #[test]
+    fn test_noise() {
+        let mut rng = Pcg64::seed_from_u64(1);
+        let ou = Noise::OrnsteinUhlenbeck { sigma: 0.5, tau: 2f64 };
+        let mut state = 0f64;
+        let samples: Vec<f64> = (0..200000).map(|_| ou.acceleration(&mut state, 10f64, 0.1, &mut rng)).collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let var = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+        assert_abs_diff_eq!(mean, 0f64, epsilon = 0.05);
+        assert_abs_diff_eq!(var.sqrt(), 0.5, epsilon = 0.05);
+
+        // Braking one second at a rate of 0.1 : about a tenth of the time is spent braking
+        let braking = Noise::RandomBraking { rate: 0.1, deceleration: 2f64, duration: 1f64 };
+        let mut state = 0f64;
+        let braked = (0..100000).filter(|_| braking.acceleration(&mut state, 10f64, 0.1, &mut rng) < 0f64).count();
+        assert_abs_diff_eq!(braked as f64 / 100000f64, 1f64 / 11f64, epsilon = 0.01);
+
+        let mut state = 1f64;
+        assert_eq!(braking.acceleration(&mut state, 0.1, 0.1, &mut rng), -1f64);
+        assert_eq!(braking.acceleration(&mut state, 0f64, 0.1, &mut rng), 0f64);
+
+        assert!(Noise::OrnsteinUhlenbeck { sigma: 1f64, tau: 0f64 }.validate().is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use crate::anticipation::Anticipation;
+use crate::model::Model;
+use crate::noise::Noise;
+use crate::scenario::positive;
+use crate::vehicle::VehicleClass;
+use crate::Car;
+use rand::distributions::WeightedIndex;
+use rand::Rng;
+use rand_distr::{Distribution as _, Normal};
+use serde::{Deserialize, Serialize};
+
+// Truncated normal draws are redrawn this many times before falling back to the clamped mean
+const MAX_REJECTIONS: usize = 1000;
+
+fn empty(min: f64, max: f64) -> bool {
+    min.is_nan() || max.is_nan() || min > max
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Distribution {
+    Fixed { value: f64 },
+    Uniform { min: f64, max: f64 },
+    Normal { mean: f64, std: f64 },
+    TruncatedNormal { mean: f64, std: f64, min: f64, max: f64 },
+    // Histogram of `counts` over the bins [edges[k], edges[k + 1]), uniform inside a bin
+    Empirical { edges: Vec<f64>, counts: Vec<f64> },
+}
+
+impl Distribution {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Distribution::Fixed { value } if value.is_nan() => Err("value should be a number".to_string()),
+            Distribution::Uniform { min, max } if empty(*min, *max) => Err(format!("range [{}, {}] is empty", min, max)),
+            Distribution::Normal { std, .. } if !std.is_finite() || *std < 0f64 => Err(format!("standard deviation {} should be finite and non-negative", std)),
+            Distribution::TruncatedNormal { std, .. } if !std.is_finite() || *std < 0f64 => Err(format!("standard deviation {} should be finite and non-negative", std)),
+            Distribution::TruncatedNormal { min, max, .. } if empty(*min, *max) => Err(format!("range [{}, {}] is empty", min, max)),
+            Distribution::Empirical { edges, counts } => {
+                if edges.len() != counts.len() + 1 {
+                    return Err(format!("{} bins need {} edges, not {}", counts.len(), counts.len() + 1, edges.len()));
+                }
+                if !edges.windows(2).all(|w| w[0] < w[1]) {
+                    return Err("edges should be increasing".to_string());
+                }
+                if !counts.iter().all(|c| *c >= 0f64) || !positive(counts.iter().sum::<f64>()) {
+                    return Err("counts should be non-negative with a positive sum".to_string());
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        match self {
+            Distribution::Fixed { value } => *value,
+            Distribution::Uniform { min, max } => min + (max - min) * rng.gen::<f64>(),
+            Distribution::Normal { mean, std } => Normal::new(*mean, *std).unwrap().sample(rng),
+            Distribution::TruncatedNormal { mean, std, min, max } => {
+                let normal = Normal::new(*mean, *std).unwrap();
+                (0..MAX_REJECTIONS)
+                    .map(|_| normal.sample(rng))
+                    .find(|x| (min..=max).contains(&x))
+                    .unwrap_or_else(|| mean.clamp(*min, *max))
+            }
+            Distribution::Empirical { edges, counts } => {
+                let k = WeightedIndex::new(counts).unwrap().sample(rng);
+                edges[k] + (edges[k + 1] - edges[k]) * rng.gen::<f64>()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriverClass {
+    #[serde(default)]
+    pub name: String,
+    // Relative weight of the class in the mix
+    pub share: f64,
+    pub size: Distribution,
+    pub drift: Distribution,
+    pub behavior: Distribution,
+    pub own_max_speed: Distribution,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<Model>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noise: Option<Noise>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vehicle: Option<VehicleClass>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anticipation: Option<Anticipation>,
+}
+
+impl DriverClass {
+    pub fn validate(&self) -> Result<(), String> {
+        if !positive(self.share) {
+            return Err(format!("share {} should be positive", self.share));
+        }
+        for (name, dist) in [("size", &self.size), ("drift", &self.drift), ("behavior", &self.behavior), ("own_max_speed", &self.own_max_speed)] {
+            dist.validate().map_err(|message| format!("{} : {}", name, message))?;
+        }
+        if let Some(model) = &self.model {
+            model.validate()?;
+        }
+        if let Some(noise) = &self.noise {
+            noise.validate()?;
+        }
+        if let Some(anticipation) = &self.anticipation {
+            anticipation.validate()?;
+        }
+        Ok(())
+    }
+
+    pub fn sample<R: Rng + ?Sized>(&self, lane: usize, rng: &mut R) -> Car {
+        // Sizes and desired speeds are kept positive whatever the tails of the distributions
+        let size = self.size.sample(rng).max(1e-3);
+        let own_max_speed = self.own_max_speed.sample(rng).max(1e-3);
+        let drift = self.drift.sample(rng);
+        let behavior = self.behavior.sample(rng);
+        let mut car = Car::new(lane, size, own_max_speed, drift, behavior, own_max_speed);
+        car.model = self.model.clone();
+        car.noise = self.noise.clone();
+        car.anticipation = self.anticipation;
+        match self.vehicle {
+            Some(class) => car.with_class(class),
+            None => car,
+        }
+    }
+
+    pub fn allows_lane(&self, lane: usize) -> bool {
+        self.vehicle.is_none_or(|v| v.allows_lane(lane))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriverMix {
+    // Cars per unit length on every lane
+    pub density: f64,
+    #[serde(default)]
+    pub vel: f64,
+    pub classes: Vec<DriverClass>,
+}
+
+impl DriverMix {
+    pub fn validate(&self) -> Result<(), String> {
+        if !positive(self.density) {
+            return Err(format!("density {} should be positive", self.density));
+        }
+        if self.classes.is_empty() {
+            return Err("mix should have at least one class".to_string());
+        }
+        for (i, class) in self.classes.iter().enumerate() {
+            class.validate().map_err(|message| format!("classes[{}] : {}", i, message))?;
+        }
+        Ok(())
+    }
+
+    pub fn sample<R: Rng + ?Sized>(&self, lane: usize, rng: &mut R) -> Option<(usize, Car)> {
+        // Index of the class along with the car, drawn among the classes allowed on the lane
+        let shares = self.classes.iter().map(|c| if c.allows_lane(lane) { c.share } else { 0f64 });
+        let k = WeightedIndex::new(shares).ok()?.sample(rng);
+        Some((k, self.classes[k].sample(lane, rng)))
+    }
+
+    pub fn place<R: Rng + ?Sized>(&self, length: f64, lanes: usize, rng: &mut R) -> Result<Vec<Car>, String> {
+        // Cars of every lane share the room left by their sizes evenly, so none overlaps on the ring.
+        // Positions are car centres, neighbours are spaced by half their sizes plus the common gap.
+        // Lanes that no class may use stay empty, restricted classes fill up the others.
+        let count = ((self.density * length).round() as usize).max(1);
+        let mut cars = Vec::with_capacity(count * lanes);
+        for lane in 0..lanes {
+            let mut lane_cars: Vec<Car> = match (0..count).map(|_| self.sample(lane, rng).map(|(_, c)| c)).collect() {
+                Some(lane_cars) => lane_cars,
+                None => continue,
+            };
+            let total: f64 = lane_cars.iter().map(|c| c.size).sum();
+            if total > length {
+                return Err(format!("{} cars of total size {} do not fit on lane {} of length {}", count, total, lane, length));
+            }
+            let gap = (length - total) / count as f64;
+            let (mut pos, mut previous) = (0f64, None);
+            for car in lane_cars.iter_mut() {
+                if let Some(size) = previous {
+                    pos += 0.5 * (size + car.size) + gap;
+                }
+                previous = Some(car.size);
+                car.pos[0] = pos;
+                car.vel[0] = self.vel;
+            }
+            cars.extend(lane_cars);
+        }
+        Ok(cars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use rand::SeedableRng;
+    use rand_pcg::Pcg64;
+
+    #[test]
+    fn test_distributions() {
+        let mut rng = Pcg64::seed_from_u64(3);
+        let truncated = Distribution::TruncatedNormal { mean: 0f64, std: 1f64, min: 0f64, max: 0.5 };
+        assert!((0..1000).all(|_| (0f64..=0.5).contains(&truncated.sample(&mut rng))));
+
+        let empirical = Distribution::Empirical { edges: vec![0f64, 1f64, 2f64], counts: vec![1f64, 3f64] };
+        let above = (0..10000).filter(|_| empirical.sample(&mut rng) >= 1f64).count();
+        assert_abs_diff_eq!(above as f64 / 10000f64, 0.75, epsilon = 0.02);
+
+        assert!(Distribution::Empirical { edges: vec![0f64, 1f64], counts: vec![1f64, 3f64] }.validate().is_err());
+        assert!(Distribution::Uniform { min: 1f64, max: 0f64 }.validate().is_err());
+        assert!(Distribution::Normal { mean: 0f64, std: f64::INFINITY }.validate().is_err());
+    }
+
+    #[test]
+    fn test_mix() {
+        // Three drivers out of ten speed by about 20 %, trucks are twice as long as cars
+        let class = |share: f64, size: f64, behavior: Distribution| DriverClass {
+            name: String::new(),
+            share,
+            size: Distribution::Fixed { value: size },
+            drift: Distribution::Fixed { value: 1f64 },
+            behavior,
+            own_max_speed: Distribution::Normal { mean: 10f64, std: 1f64 },
+            model: None,
+            noise: None,
+            vehicle: None,
+            anticipation: None,
+        };
+        let mix = DriverMix {
+            density: 0.1,
+            vel: 5f64,
+            classes: vec![
+                class(0.7, 4f64, Distribution::Fixed { value: 0f64 }),
+                class(0.3, 8f64, Distribution::TruncatedNormal { mean: 0.2, std: 0.05, min: 0f64, max: 0.5 }),
+            ],
+        };
+        assert!(mix.validate().is_ok());
+
+        let mut rng = Pcg64::seed_from_u64(11);
+        let cars = mix.place(1000f64, 2, &mut rng).unwrap();
+        assert_eq!(cars.len(), 200);
+        let speeding = cars.iter().filter(|c| c.behavior > 0f64).count();
+        assert_abs_diff_eq!(speeding as f64 / 200f64, 0.3, epsilon = 0.08);
+        for lane in 0..2 {
+            let on_lane: Vec<&Car> = cars.iter().filter(|c| c.lane == lane).collect();
+            assert!(on_lane.windows(2).any(|w| w[0].size != w[1].size));
+            assert!(on_lane.windows(2).all(|w| w[1].pos[0] - w[0].pos[0] > 0.5 * (w[0].size + w[1].size)));
+            let (first, last) = (on_lane[0], *on_lane.last().unwrap());
+            assert!(last.pos[0] < 1000f64);
+            assert!(first.pos[0] + 1000f64 - last.pos[0] > 0.5 * (first.size + last.size));
+        }
+
+        // Trucks keep to the two right lanes, the third one only gets cars
+        let mut restricted = mix.clone();
+        restricted.classes[1].vehicle = Some(VehicleClass::Truck);
+        let cars = restricted.place(1000f64, 3, &mut rng).unwrap();
+        assert_eq!(cars.len(), 300);
+        assert!(cars.iter().filter(|c| c.lane == 2).all(|c| c.class() == VehicleClass::Car && c.size == 4f64));
+        assert!(cars.iter().any(|c| c.class() == VehicleClass::Truck));
+
+        // A mix of trucks only cannot be packed this densely
+        let mut trucks = DriverMix { density: 0.2, vel: 0f64, classes: vec![class(1f64, 8f64, Distribution::Fixed { value: 0f64 })] };
+        assert!(trucks.place(1000f64, 1, &mut rng).is_err());
+        trucks.density = 0.05;
+        trucks.classes[0].vehicle = Some(VehicleClass::Truck);
+        assert_eq!(trucks.place(1000f64, 3, &mut rng).unwrap().len(), 100);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{CarId, Simulation};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"TRAJ";
+const VERSION: u32 = 1;
+// Longest header accepted when reading, the scenario it holds is far smaller
+const MAX_HEADER: u64 = 1 << 24;
+
+pub trait Observer {
+    fn observe(&mut self, sim: &Simulation);
+}
+
+impl<O: Observer + ?Sized> Observer for &mut O {
+    fn observe(&mut self, sim: &Simulation) {
+        (**self).observe(sim);
+    }
+}
+
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn observe(&mut self, sim: &Simulation) {
+        self.0.observe(sim);
+        self.1.observe(sim);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub time: f64,
+    pub car: CarId,
+    pub pos: f64,
+    pub vel: f64,
+    pub lane: usize,
+    pub max_speed: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    interval: f64,
+    next: Option<f64>,
+    header: String,
+    samples: Vec<Sample>,
+}
+
+impl Recorder {
+    pub fn new(interval: f64) -> Self {
+        if interval.is_nan() || interval < 0f64 {
+            panic!("Invalid sampling interval : {} should be non-negative", interval);
+        }
+        Self {
+            interval,
+            next: None,
+            header: String::new(),
+            samples: vec![],
+        }
+    }
+
+    pub fn with_header(mut self, header: String) -> Self {
+        self.header = header;
+        self
+    }
+
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn record<I: IntoIterator<Item = Sample>>(&mut self, time: f64, dt: f64, samples: I) {
+        // Sampling starts at the first time seen, which need not be zero for a resumed run.
+        // Half a step of slack keeps round-off in the clock from skipping a sample
+        let next = *self.next.get_or_insert(time);
+        if time + 0.5 * dt < next {
+            return;
+        }
+        self.samples.extend(samples);
+        // The next sample lies whole intervals ahead of the last one, past the current time
+        self.next = Some(if self.interval > 0f64 {
+            next + self.interval * (((time + 0.5 * dt - next) / self.interval).floor() + 1f64)
+        } else {
+            time
+        });
+    }
+
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        // The header is kept as comment lines so that csv readers can skip it
+        for line in self.header.lines() {
+            writeln!(writer, "# {}", line)?;
+        }
+        writeln!(writer, "time,car,pos,vel,lane,max_speed")?;
+        for s in self.samples.iter() {
+            writeln!(writer, "{},{},{},{},{},{}", s.time, s.car, s.pos, s.vel, s.lane, s.max_speed)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_binary<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        // Little endian layout :
+        //   b"TRAJ", version : u32, header length : u64, header : utf-8, sample count : u64,
+        //   then per sample time : f64, car : u64, pos : f64, vel : f64, lane : u64, max_speed : f64
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.header.len() as u64).to_le_bytes())?;
+        writer.write_all(self.header.as_bytes())?;
+        writer.write_all(&(self.samples.len() as u64).to_le_bytes())?;
+        for s in self.samples.iter() {
+            writer.write_all(&s.time.to_le_bytes())?;
+            writer.write_all(&(s.car.0 as u64).to_le_bytes())?;
+            writer.write_all(&s.pos.to_le_bytes())?;
+            writer.write_all(&s.vel.to_le_bytes())?;
+            writer.write_all(&(s.lane as u64).to_le_bytes())?;
+            writer.write_all(&s.max_speed.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_binary<R: Read>(mut reader: R) -> io::Result<(String, Vec<Sample>)> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let mut u64_buf = [0u8; 8];
+        let mut read_u64 = |reader: &mut R| -> io::Result<u64> {
+            reader.read_exact(&mut u64_buf)?;
+            Ok(u64::from_le_bytes(u64_buf))
+        };
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a trajectory file"));
+        }
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        if u32::from_le_bytes(version) != VERSION {
+            return Err(invalid("unsupported trajectory version"));
+        }
+
+        // Lengths come from the file, so nothing is allocated before the bytes are actually there
+        let len = read_u64(&mut reader)?;
+        if len > MAX_HEADER {
+            return Err(invalid("header is too long"));
+        }
+        let mut header = vec![];
+        if (&mut reader).take(len).read_to_end(&mut header)? as u64 != len {
+            return Err(invalid("file ends inside the header"));
+        }
+        let header = String::from_utf8(header).map_err(|_| invalid("header is not utf-8"))?;
+
+        let n = read_u64(&mut reader)?;
+        let mut samples = vec![];
+        let truncated = |e: io::Error| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("file ends before the last sample"),
+            _ => e,
+        };
+        for _ in 0..n {
+            let mut read = || -> io::Result<Sample> {
+                Ok(Sample {
+                    time: f64::from_bits(read_u64(&mut reader)?),
+                    car: CarId(read_u64(&mut reader)? as usize),
+                    pos: f64::from_bits(read_u64(&mut reader)?),
+                    vel: f64::from_bits(read_u64(&mut reader)?),
+                    lane: read_u64(&mut reader)? as usize,
+                    max_speed: f64::from_bits(read_u64(&mut reader)?),
+                })
+            };
+            samples.push(read().map_err(truncated)?);
+        }
+        Ok((header, samples))
+    }
+}
+
+impl Observer for Recorder {
+    fn observe(&mut self, sim: &Simulation) {
+        let samples = sim.cars().map(|c| Sample {
+            time: sim.time(),
+            car: c.listed_id(),
+            pos: c.pos[0],
+            vel: c.vel[0],
+            lane: c.lane,
+            max_speed: c.max_speed,
+        });
+        self.record(sim.time(), sim.dt(), samples);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Car, Road, TrafficItem, TrafficList};
+
+    #[test]
+    fn test_recorder() {
+        let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        car.pos[0] = 50f64;
+        let items = vec![
+            TrafficItem::Car(Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64)),
+            TrafficItem::Car(car),
+        ];
+        let mut sim = Simulation::new(Road::try_from(TrafficList::new(items)).unwrap(), 100f64, 1e-1);
+        let mut recorder = Recorder::new(1f64).with_header("{\"scenario\": 1}".to_string());
+        sim.run_observed(10f64, &mut recorder);
+
+        let samples = recorder.samples();
+        assert_eq!(samples.len(), 2 * 11);
+        assert_eq!(samples[2].car, CarId(0));
+        assert_eq!(samples[3].car, CarId(1));
+        assert!((samples[20].time - 10f64).abs() < 1e-9);
+
+        let mut csv = vec![];
+        recorder.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().next().unwrap(), "# {\"scenario\": 1}");
+        assert_eq!(csv.lines().count(), 2 + samples.len());
+
+        let mut bin = vec![];
+        recorder.write_binary(&mut bin).unwrap();
+        let (header, read) = Recorder::read_binary(&bin[..]).unwrap();
+        assert_eq!(header, recorder.header());
+        assert_eq!(read, samples);
+
+        // A recorder attached to a run already under way samples it at evenly spaced times from there on
+        let mut resumed = Recorder::new(1f64);
+        sim.run_observed(15f64, &mut resumed);
+        let times: Vec<f64> = resumed.samples().iter().step_by(2).map(|s| s.time).collect();
+        assert_eq!(times.len(), 6);
+        assert!(times.windows(2).all(|w| (w[1] - w[0] - 1f64).abs() < 1e-9));
+
+        // Corrupt lengths are reported instead of being allocated
+        let header_at = 8;
+        let count_at = header_at + 8 + recorder.header().len();
+        let mut corrupt = bin.clone();
+        corrupt[header_at..header_at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(Recorder::read_binary(&corrupt[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut corrupt = bin.clone();
+        corrupt[count_at..count_at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(Recorder::read_binary(&corrupt[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,792 @@
+use crate::anticipation::Anticipation;
+use crate::cellular::{CellZone, CellularRoad, CellularSpec};
+use crate::collision::CollisionPolicy;
+use crate::inflow::{Arrivals, Inflow, SpeedDistribution};
+use crate::infrastructure::{Fixture, FixtureId, FixtureKind};
+pub use crate::integrator::IntegratorKind;
+use crate::integrator::Adaptive;
+use crate::lane::Mobil;
+use crate::limits::Limits;
+use crate::model::Model;
+use crate::noise::Noise;
+use crate::population::DriverMix;
+use crate::vehicle::VehicleClass;
+use crate::{Car, Road, Simulation, SpeedCam};
+use moldybrody::prelude::*;
+use rand::SeedableRng;
+use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Parse { field: String, message: String },
+    Invalid { field: String, message: String },
+    UnknownFormat(String),
+}
+
+impl ScenarioError {
+    pub(crate) fn invalid(field: String, message: String) -> Self {
+        ScenarioError::Invalid { field, message }
+    }
+
+    pub(crate) fn parse<E: fmt::Display>(e: serde_path_to_error::Error<E>) -> Self {
+        ScenarioError::Parse {
+            field: e.path().to_string(),
+            message: e.inner().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "cannot read scenario : {}", e),
+            ScenarioError::Parse { field, message } => write!(f, "cannot parse scenario at `{}` : {}", field, message),
+            ScenarioError::Invalid { field, message } => write!(f, "invalid scenario at `{}` : {}", field, message),
+            ScenarioError::UnknownFormat(ext) => write!(f, "unknown scenario format `{}` : expected toml or json", ext),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<std::io::Error> for ScenarioError {
+    fn from(e: std::io::Error) -> Self {
+        ScenarioError::Io(e)
+    }
+}
+
+pub(crate) fn positive(x: f64) -> bool {
+    x > 0f64
+}
+
+fn one() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BoundaryKind {
+    #[default]
+    Periodic,
+    Open,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoadSpec {
+    pub length: f64,
+    #[serde(default)]
+    pub boundary: BoundaryKind,
+    #[serde(default = "one")]
+    pub lanes: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegratorSpec {
+    #[serde(default)]
+    pub method: IntegratorKind,
+    pub dt: f64,
+    pub tmax: f64,
+    // Only used by the adaptive method
+    #[serde(default)]
+    pub adaptive: Adaptive,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CarParams {
+    #[serde(default)]
+    pub lane: usize,
+    pub size: f64,
+    pub max_speed: f64,
+    pub drift: f64,
+    #[serde(default)]
+    pub behavior: f64,
+    pub own_max_speed: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<Model>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noise: Option<Noise>,
+    // Mass, capabilities and lane restriction of the class preset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<VehicleClass>,
+    // Overrides the limits of the class
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<Limits>,
+    // How the driver reacts to the cameras in sight
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anticipation: Option<Anticipation>,
+}
+
+impl CarParams {
+    pub fn build(&self, pos: f64, vel: f64) -> Car {
+        let mut car = Car::new(self.lane, self.size, self.max_speed, self.drift, self.behavior, self.own_max_speed);
+        if let Some(model) = &self.model {
+            car = car.with_model(model.clone());
+        }
+        if let Some(noise) = &self.noise {
+            car = car.with_noise(noise.clone());
+        }
+        if let Some(class) = self.class {
+            car = car.with_class(class);
+        }
+        if let Some(limits) = self.limits {
+            car = car.with_limits(limits);
+        }
+        if let Some(anticipation) = self.anticipation {
+            car = car.with_anticipation(anticipation);
+        }
+        car.pos[0] = pos;
+        car.vel[0] = vel;
+        car
+    }
+
+    pub(crate) fn validate(&self, field: &str, lanes: usize) -> Result<(), ScenarioError> {
+        if self.lane >= lanes {
+            return Err(ScenarioError::invalid(format!("{}.lane", field), format!("lane {} does not exist on a road with {} lanes", self.lane, lanes)));
+        }
+        if let Some(class) = self.class {
+            if !class.allows_lane(self.lane) {
+                return Err(ScenarioError::invalid(format!("{}.lane", field), format!("class {:?} may not use lane {}", class, self.lane)));
+            }
+        }
+        if !positive(self.size) {
+            return Err(ScenarioError::invalid(format!("{}.size", field), format!("size {} should be positive", self.size)));
+        }
+        if !positive(self.own_max_speed) {
+            return Err(ScenarioError::invalid(format!("{}.own_max_speed", field), format!("speed {} should be positive", self.own_max_speed)));
+        }
+        if let Some(model) = &self.model {
+            model.validate().map_err(|message| ScenarioError::invalid(format!("{}.model", field), message))?;
+        }
+        if let Some(noise) = &self.noise {
+            noise.validate().map_err(|message| ScenarioError::invalid(format!("{}.noise", field), message))?;
+        }
+        if let Some(limits) = &self.limits {
+            limits.validate().map_err(|message| ScenarioError::invalid(format!("{}.limits", field), message))?;
+        }
+        if let Some(anticipation) = &self.anticipation {
+            anticipation.validate().map_err(|message| ScenarioError::invalid(format!("{}.anticipation", field), message))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CarSpec {
+    pub pos: f64,
+    #[serde(default)]
+    pub vel: f64,
+    #[serde(flatten)]
+    pub params: CarParams,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PopulationSpec {
+    pub count: usize,
+    #[serde(default)]
+    pub start: f64,
+    pub end: Option<f64>,
+    #[serde(default)]
+    pub vel: f64,
+    #[serde(flatten)]
+    pub params: CarParams,
+}
+
+impl PopulationSpec {
+    pub fn build(&self, length: f64) -> Vec<Car> {
+        // Cars are spread evenly over [start, end)
+        let end = self.end.unwrap_or(length);
+        let spacing = (end - self.start) / self.count as f64;
+        (0..self.count)
+            .map(|k| self.params.build(self.start + spacing * k as f64, self.vel))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InflowSpec {
+    pub arrivals: Arrivals,
+    pub speed: SpeedDistribution,
+    // Index of the on ramp in `fixtures` the cars enter through, the upstream end if None
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ramp: Option<usize>,
+    // Index of the off ramp in `fixtures` the cars leave by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<usize>,
+    #[serde(flatten)]
+    pub params: CarParams,
+}
+
+impl InflowSpec {
+    pub fn build(&self) -> Inflow {
+        let mut template = self.params.build(0f64, 0f64);
+        if let Some(ramp) = self.destination {
+            template = template.with_destination(FixtureId(ramp));
+        }
+        let inflow = Inflow::new(self.arrivals.clone(), self.speed.clone(), template);
+        match self.ramp {
+            Some(ramp) => inflow.with_ramp(FixtureId(ramp)),
+            None => inflow,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraSpec {
+    pub pos: f64,
+    pub speed_limit: f64,
+    pub length: f64,
+    #[serde(default)]
+    pub check_average: bool,
+    #[serde(default = "crate::default_tolerance")]
+    pub tolerance: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub class_limits: Vec<ClassLimit>,
+    // Drivers who anticipate cameras see this one from that far before its zone
+    #[serde(default)]
+    pub sight_distance: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClassLimit {
+    pub class: VehicleClass,
+    pub speed_limit: f64,
+}
+
+impl CameraSpec {
+    pub fn build(&self) -> SpeedCam {
+        let cam = SpeedCam::new(Cartessian1D::new([self.pos]), self.speed_limit, self.length, self.check_average)
+            .with_tolerance(self.tolerance)
+            .with_sight_distance(self.sight_distance);
+        self.class_limits.iter().fold(cam, |cam, l| cam.with_class_limit(l.class, l.speed_limit))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scenario {
+    pub road: RoadSpec,
+    pub integrator: IntegratorSpec,
+    #[serde(default)]
+    pub cars: Vec<CarSpec>,
+    #[serde(default)]
+    pub populations: Vec<PopulationSpec>,
+    // Cars drawn from a mix of driver classes, placed at the density of the mix on every lane
+    #[serde(default)]
+    pub mixes: Vec<DriverMix>,
+    #[serde(default)]
+    pub cameras: Vec<CameraSpec>,
+    // Only on an open road, unless they come through an on ramp
+    #[serde(default)]
+    pub inflows: Vec<InflowSpec>,
+    // Signs, lights, lane drops and ramps along the road
+    #[serde(default)]
+    pub fixtures: Vec<Fixture>,
+    pub lane_change: Option<Mobil>,
+    // Car-following model of the cars that do not set their own
+    #[serde(default)]
+    pub model: Model,
+    // Acceleration and braking limits of the cars that do not set their own, unbounded without them
+    #[serde(default)]
+    pub limits: Option<Limits>,
+    // Parameters of the cellular automaton run of the same scenario
+    pub cellular: Option<CellularSpec>,
+    // Seed of the random numbers. Without one the library uses zero, the command line draws a fresh one.
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub collisions: CollisionPolicy,
+}
+
+impl Scenario {
+    pub fn from_json(s: &str) -> Result<Self, ScenarioError> {
+        let scenario: Self = serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(s))
+            .map_err(ScenarioError::parse)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, ScenarioError> {
+        let scenario: Self = serde_path_to_error::deserialize(&mut toml::Deserializer::new(s))
+            .map_err(ScenarioError::parse)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScenarioError> {
+        let path = path.as_ref();
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match ext {
+            "toml" => Self::from_toml(&std::fs::read_to_string(path)?),
+            "json" => Self::from_json(&std::fs::read_to_string(path)?),
+            _ => Err(ScenarioError::UnknownFormat(ext.to_string())),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        // Going through a value puts plain values ahead of tables as toml requires
+        toml::to_string(&toml::Value::try_from(self)?)
+    }
+
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let length = self.road.length;
+        if !positive(length) {
+            return Err(ScenarioError::invalid("road.length".to_string(), format!("length {} should be positive", length)));
+        }
+        if self.road.lanes == 0 {
+            return Err(ScenarioError::invalid("road.lanes".to_string(), "road should have at least one lane".to_string()));
+        }
+        if !positive(self.integrator.dt) {
+            return Err(ScenarioError::invalid("integrator.dt".to_string(), format!("time step {} should be positive", self.integrator.dt)));
+        }
+        if self.integrator.tmax.is_nan() || self.integrator.tmax < 0f64 {
+            return Err(ScenarioError::invalid("integrator.tmax".to_string(), format!("tmax {} should be non-negative", self.integrator.tmax)));
+        }
+        // Toml integers are signed, a larger seed could not be written back to a scenario file
+        if let Some(seed) = self.seed.filter(|&seed| seed > i64::MAX as u64) {
+            return Err(ScenarioError::invalid("seed".to_string(), format!("seed {} should be at most {}", seed, i64::MAX)));
+        }
+        self.integrator.adaptive.validate().map_err(|message| ScenarioError::invalid("integrator.adaptive".to_string(), message))?;
+        self.model.validate().map_err(|message| ScenarioError::invalid("model".to_string(), message))?;
+        if let Some(limits) = &self.limits {
+            limits.validate().map_err(|message| ScenarioError::invalid("limits".to_string(), message))?;
+        }
+        if let Some(spec) = &self.cellular {
+            spec.validate().map_err(|message| ScenarioError::invalid("cellular".to_string(), message))?;
+        }
+
+        for (i, car) in self.cars.iter().enumerate() {
+            let field = format!("cars[{}]", i);
+            car.params.validate(&field, self.road.lanes)?;
+            if !(0f64..length).contains(&car.pos) {
+                return Err(ScenarioError::invalid(format!("{}.pos", field), format!("position {} lies outside of the road", car.pos)));
+            }
+        }
+        for (i, pop) in self.populations.iter().enumerate() {
+            let field = format!("populations[{}]", i);
+            pop.params.validate(&field, self.road.lanes)?;
+            let end = pop.end.unwrap_or(length);
+            if !(0f64 <= pop.start && pop.start < end && end <= length) {
+                return Err(ScenarioError::invalid(format!("{}.end", field), format!("segment [{}, {}) lies outside of the road", pop.start, end)));
+            }
+        }
+        for (i, mix) in self.mixes.iter().enumerate() {
+            mix.validate().map_err(|message| ScenarioError::invalid(format!("mixes[{}]", i), message))?;
+        }
+        for (i, inflow) in self.inflows.iter().enumerate() {
+            let field = format!("inflows[{}]", i);
+            match inflow.ramp {
+                Some(ramp) if !matches!(self.fixtures.get(ramp), Some(f) if matches!(f.kind, FixtureKind::OnRamp { .. })) => {
+                    return Err(ScenarioError::invalid(format!("{}.ramp", field), format!("fixture {} is not an on ramp", ramp)));
+                }
+                None if self.road.boundary != BoundaryKind::Open => {
+                    return Err(ScenarioError::invalid(field, "cars only enter a road with an open boundary".to_string()));
+                }
+                _ => {}
+            }
+            if let Some(ramp) = inflow.destination.filter(|&r| !matches!(self.fixtures.get(r), Some(f) if matches!(f.kind, FixtureKind::OffRamp { .. }))) {
+                return Err(ScenarioError::invalid(format!("{}.destination", field), format!("fixture {} is not an off ramp", ramp)));
+            }
+            inflow.params.validate(&field, self.road.lanes)?;
+            inflow.arrivals.validate().map_err(|message| ScenarioError::invalid(format!("{}.arrivals", field), message))?;
+            inflow.speed.validate().map_err(|message| ScenarioError::invalid(format!("{}.speed", field), message))?;
+        }
+        for (i, cam) in self.cameras.iter().enumerate() {
+            let field = format!("cameras[{}]", i);
+            if !(positive(cam.length) && cam.length <= cam.pos && cam.pos < length) {
+                return Err(ScenarioError::invalid(format!("{}.length", field), format!("zone [{}, {}] lies outside of the road", cam.pos - cam.length, cam.pos)));
+            }
+            if !positive(cam.speed_limit) {
+                return Err(ScenarioError::invalid(format!("{}.speed_limit", field), format!("speed limit {} should be positive", cam.speed_limit)));
+            }
+            if !positive(cam.tolerance) {
+                return Err(ScenarioError::invalid(format!("{}.tolerance", field), format!("tolerance {} should be positive", cam.tolerance)));
+            }
+            if let Some(l) = cam.class_limits.iter().find(|l| !positive(l.speed_limit)) {
+                return Err(ScenarioError::invalid(format!("{}.class_limits", field), format!("speed limit {} of class {:?} should be positive", l.speed_limit, l.class)));
+            }
+        }
+        for (i, fixture) in self.fixtures.iter().enumerate() {
+            let field = format!("fixtures[{}]", i);
+            if !(0f64..length).contains(&fixture.pos) {
+                return Err(ScenarioError::invalid(format!("{}.pos", field), format!("position {} lies outside of the road", fixture.pos)));
+            }
+            if matches!(fixture.merge_end(), Some(end) if end > length) {
+                return Err(ScenarioError::invalid(format!("{}.merge", field), "the acceleration lane goes past the end of the road".to_string()));
+            }
+            if matches!(fixture.kind, FixtureKind::LaneDrop { lane } if lane >= self.road.lanes) {
+                return Err(ScenarioError::invalid(format!("{}.lane", field), format!("lane does not exist on a road with {} lanes", self.road.lanes)));
+            }
+            fixture.validate().map_err(|message| ScenarioError::invalid(field, message))?;
+        }
+
+        self.check_overlaps()
+    }
+
+    fn labeled_cars(&self) -> Result<Vec<(String, Car)>, ScenarioError> {
+        // Mixes are drawn from the seed of the scenario, so a run places the cars that were validated
+        let mut cars: Vec<(String, Car)> = self
+            .cars
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (format!("cars[{}]", i), c.params.build(c.pos, c.vel)))
+            .collect();
+        for (i, pop) in self.populations.iter().enumerate() {
+            cars.extend(pop.build(self.road.length).into_iter().map(|c| (format!("populations[{}]", i), c)));
+        }
+        let mut rng = Pcg64::seed_from_u64(self.seed.unwrap_or(0));
+        for (i, mix) in self.mixes.iter().enumerate() {
+            let field = format!("mixes[{}]", i);
+            let placed = mix.place(self.road.length, self.road.lanes, &mut rng).map_err(|message| ScenarioError::invalid(field.clone(), message))?;
+            cars.extend(placed.into_iter().map(|c| (field.clone(), c)));
+        }
+        Ok(cars)
+    }
+
+    fn check_overlaps(&self) -> Result<(), ScenarioError> {
+        let mut cars = self.labeled_cars()?;
+        cars.sort_by(|(_, a), (_, b)| (a.lane, a.pos[0]).partial_cmp(&(b.lane, b.pos[0])).unwrap());
+
+        // Positions are car centres, two cars overlap when they are closer than half their sizes
+        let overlap = |(_, rear): &(String, Car), (field, front): &(String, Car), distance: f64| {
+            if distance < 0.5 * (rear.size + front.size) {
+                return Err(ScenarioError::invalid(field.clone(), format!("car at {} overlaps the car at {} on lane {}", front.pos[0], rear.pos[0], front.lane)));
+            }
+            Ok(())
+        };
+        for lane in cars.chunk_by(|(_, a), (_, b)| a.lane == b.lane) {
+            for w in lane.windows(2) {
+                overlap(&w[0], &w[1], w[1].1.pos[0] - w[0].1.pos[0])?;
+            }
+            // On a ring the last car of the lane also follows the first one across the seam
+            if self.road.boundary == BoundaryKind::Periodic && lane.len() > 1 {
+                let (first, last) = (&lane[0], &lane[lane.len() - 1]);
+                overlap(last, first, first.1.pos[0] + self.road.length - last.1.pos[0])?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn build_cars(&self) -> Result<Vec<Car>, ScenarioError> {
+        Ok(self.labeled_cars()?.into_iter().map(|(_, c)| c).collect())
+    }
+
+    pub fn speed_cams(&self) -> Vec<SpeedCam> {
+        self.cameras.iter().map(|c| c.build()).collect()
+    }
+
+    pub fn build_road(&self) -> Result<Road, ScenarioError> {
+        Road::from_cars(self.build_cars()?, self.speed_cams())
+            .map_err(|message| ScenarioError::invalid("cars".to_string(), message))?
+            .with_fixtures(self.fixtures.clone())
+            .map_err(|message| ScenarioError::invalid("fixtures".to_string(), message))
+    }
+
+    pub fn simulation(&self) -> Result<Simulation, ScenarioError> {
+        // Fields may have been changed since the scenario was read, the builders below would panic on them
+        self.validate()?;
+        let sim = Simulation::new(self.build_road()?, self.road.length, self.integrator.dt)
+            .with_lanes(self.road.lanes)
+            .try_with_model(self.model.clone())
+            .map_err(|message| ScenarioError::invalid("model".to_string(), message))?
+            .with_seed(self.seed.unwrap_or(0))
+            .with_collision_policy(self.collisions)
+            .with_integrator(self.integrator.method)
+            .with_adaptive(self.integrator.adaptive);
+        let sim = match self.road.boundary {
+            BoundaryKind::Periodic => sim,
+            BoundaryKind::Open => sim.with_open_boundary(),
+        };
+        let sim = match self.limits {
+            Some(limits) => sim.with_limits(limits),
+            None => sim,
+        };
+        let sim = self.inflows.iter().fold(sim, |sim, inflow| sim.with_inflow(inflow.build()));
+        Ok(match &self.lane_change {
+            Some(rule) => sim.with_lane_change(rule.clone()),
+            None => sim,
+        })
+    }
+
+    pub fn cellular_road(&self) -> Result<CellularRoad, ScenarioError> {
+        // Positions and speeds are rounded down to whole cells, two cars may not share a cell
+        if self.road.boundary != BoundaryKind::Periodic {
+            return Err(ScenarioError::invalid("road.boundary".to_string(), "the cellular automaton runs on a periodic road".to_string()));
+        }
+        if !self.fixtures.is_empty() {
+            return Err(ScenarioError::invalid("fixtures".to_string(), "the cellular automaton has no fixtures".to_string()));
+        }
+        let mut spec = self.cellular.clone().unwrap_or_default();
+        if let Some(seed) = self.seed {
+            spec.seed = seed;
+        }
+        spec.validate().map_err(|message| ScenarioError::invalid("cellular".to_string(), message))?;
+        let cells = (self.road.length / spec.cell_length).floor() as usize;
+        if cells == 0 {
+            return Err(ScenarioError::invalid("cellular.cell_length".to_string(), format!("cell length {} exceeds the road length {}", spec.cell_length, self.road.length)));
+        }
+        let mut road = CellularRoad::new(cells, self.road.lanes, spec.clone());
+        for (i, cam) in self.cameras.iter().enumerate() {
+            let zone = CellZone::from_cam(cam, spec.cell_length).map_err(|message| ScenarioError::invalid(format!("cameras[{}]", i), message))?;
+            if zone.start >= zone.end || zone.end >= cells {
+                return Err(ScenarioError::invalid(format!("cameras[{}]", i), format!("zone [{}, {}] does not cover a whole cell of the road", cam.pos - cam.length, cam.pos)));
+            }
+            road = road.with_zone(zone);
+        }
+        for (field, car) in self.labeled_cars()? {
+            let cell = (car.pos[0] / spec.cell_length).floor() as usize;
+            if road.insert(car.lane, cell, spec.cells(car.vel[0]), spec.cells(car.own_max_speed), car.behavior).is_none() {
+                return Err(ScenarioError::invalid(field, format!("car at {} shares cell {} with another car", car.pos[0], cell)));
+            }
+        }
+        Ok(road)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Idm;
+    use crate::population::{DriverClass, Distribution};
+
+    const SCENARIO: &str = r#"{
+        "road": {"length": 1000.0},
+        "integrator": {"dt": 0.1, "tmax": 10.0},
+        "cars": [
+            {"pos": 50.0, "size": 1.0, "max_speed": 10.0, "drift": 1.0, "own_max_speed": 10.0},
+            {"pos": 10.0, "size": 1.0, "max_speed": 10.0, "drift": 1.0, "own_max_speed": 10.0}
+        ],
+        "cameras": [
+            {"pos": 500.0, "speed_limit": 5.0, "length": 100.0}
+        ]
+    }"#;
+
+    const SCENARIO_TOML: &str = r#"
+        [road]
+        length = 1000.0
+        lanes = 2
+
+        [integrator]
+        method = "euler"
+        dt = 0.1
+        tmax = 10.0
+
+        [[populations]]
+        count = 10
+        start = 600.0
+        lane = 1
+        size = 1.0
+        max_speed = 10.0
+        drift = 1.0
+        own_max_speed = 10.0
+        model = { type = "krauss", min_gap = 1.0 }
+
+        [[cameras]]
+        pos = 500.0
+        speed_limit = 5.0
+        length = 100.0
+        tolerance = 1.2
+        class_limits = [{ class = "truck", speed_limit = 4.0 }]
+
+        [lane_change]
+        politeness = 0.2
+
+        [model]
+        type = "idm"
+        time_headway = 1.0
+    "#;
+
+    #[test]
+    fn test_scenario_json() {
+        let scenario = Scenario::from_json(SCENARIO).unwrap();
+        let mut sim = scenario.simulation().unwrap();
+
+        assert_eq!(sim.traffic().len(), 4);
+        let pos: Vec<f64> = sim.traffic().iter().map(|item| item.pos()[0]).collect();
+        assert_eq!(pos, vec![10f64, 50f64, 400f64, 500f64]);
+
+        sim.run_until(scenario.integrator.tmax);
+        assert_eq!(sim.cars().count(), 2);
+    }
+
+    #[test]
+    fn test_scenario_toml() {
+        let scenario = Scenario::from_toml(SCENARIO_TOML).unwrap();
+        assert_eq!(scenario.road.lanes, 2);
+        assert_eq!(scenario.road.boundary, BoundaryKind::Periodic);
+        let mobil = scenario.lane_change.as_ref().unwrap();
+        assert_eq!(mobil.politeness, 0.2);
+        assert_eq!(mobil.threshold, Mobil::default().threshold);
+        assert!(matches!(&scenario.model, Model::Idm(idm) if idm.time_headway == 1.0));
+
+        assert_eq!(scenario.speed_cams()[0].tolerance(), 1.2);
+        assert_eq!(scenario.speed_cams()[0].limit_for(VehicleClass::Truck), 4.0);
+        assert_eq!(scenario.speed_cams()[0].limit_for(VehicleClass::Bus), 5.0);
+
+        let cars = scenario.build_cars().unwrap();
+        assert_eq!(cars.len(), 10);
+        assert_eq!(cars[1].pos[0], 640f64);
+
+        let again = Scenario::from_toml(&scenario.to_toml().unwrap()).unwrap();
+        assert_eq!(again.build_cars().unwrap().len(), 10);
+        let again = Scenario::from_json(&scenario.to_json()).unwrap();
+        assert_eq!(again.populations[0].params.lane, 1);
+        assert!(matches!(again.build_cars().unwrap()[0].model(), Some(Model::Krauss(k)) if k.min_gap == 1.0));
+        assert_eq!(again.model, scenario.model);
+    }
+
+    #[test]
+    fn test_scenario_invalid() {
+        let mut scenario = Scenario::from_json(SCENARIO).unwrap();
+        scenario.cameras[0].length = 600f64;
+        match scenario.validate() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "cameras[0].length"),
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        let mut scenario = Scenario::from_json(SCENARIO).unwrap();
+        scenario.cars[1].pos = 49.5;
+        match scenario.validate() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "cars[0]"),
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        // A scenario edited in code is checked again before it is run
+        let mut scenario = Scenario::from_json(SCENARIO).unwrap();
+        scenario.road.lanes = 0;
+        match scenario.simulation() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "road.lanes"),
+            e => panic!("unexpected result {:?}", e.map(|sim| sim.time())),
+        }
+
+        let mut scenario = Scenario::from_json(SCENARIO).unwrap();
+        scenario.model = Model::Idm(Idm { min_gap: 0f64, ..Idm::default() });
+        match scenario.simulation() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "model"),
+            e => panic!("unexpected result {:?}", e.map(|sim| sim.time())),
+        }
+
+        // Centres closer than half the sizes overlap, also across the seam of the ring
+        let mut scenario = Scenario::from_json(SCENARIO).unwrap();
+        scenario.cars[0].params.size = 12f64;
+        scenario.cars[1].params.size = 4f64;
+        scenario.cars[1].pos = 45f64;
+        match scenario.validate() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "cars[0]"),
+            e => panic!("unexpected result {:?}", e),
+        }
+        scenario.cars[1].pos = 42f64;
+        assert!(scenario.validate().is_ok());
+        scenario.cars[0].params.size = 1f64;
+        scenario.cars[0].pos = 999.5;
+        scenario.cars[1].pos = 1f64;
+        match scenario.validate() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "cars[1]"),
+            e => panic!("unexpected result {:?}", e),
+        }
+        scenario.road.boundary = BoundaryKind::Open;
+        assert!(scenario.validate().is_ok());
+
+        // Seeds have to fit a toml integer so that the scenario of a run can be written back
+        let mut scenario = Scenario::from_json(SCENARIO).unwrap();
+        scenario.seed = Some(i64::MAX as u64);
+        assert!(Scenario::from_toml(&scenario.to_toml().unwrap()).is_ok());
+        scenario.seed = Some(u64::MAX);
+        match scenario.validate() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "seed"),
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        let mut scenario = Scenario::from_json(SCENARIO).unwrap();
+        scenario.cars[1].pos = 52f64;
+        match scenario.cellular_road() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "cars[1]"),
+            e => panic!("unexpected result {:?}", e.map(|road| road.vehicles().len())),
+        }
+        scenario.cellular = Some(CellularSpec { cell_length: 2000f64, ..CellularSpec::default() });
+        match scenario.cellular_road() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "cellular.cell_length"),
+            e => panic!("unexpected result {:?}", e.map(|road| road.vehicles().len())),
+        }
+
+        let mut scenario = Scenario::from_json(SCENARIO).unwrap();
+        scenario.inflows.push(InflowSpec {
+            arrivals: Arrivals::Poisson { rate: 0.2 },
+            speed: SpeedDistribution::Fixed { speed: 10f64 },
+            params: scenario.cars[0].params.clone(),
+            ramp: None,
+            destination: None,
+        });
+        match scenario.validate() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "inflows[0]"),
+            e => panic!("unexpected result {:?}", e),
+        }
+        scenario.road.boundary = BoundaryKind::Open;
+        assert!(scenario.validate().is_ok());
+        assert_eq!(scenario.simulation().unwrap().inflows().len(), 1);
+
+        scenario.road.boundary = BoundaryKind::Periodic;
+        scenario.inflows[0].ramp = Some(1);
+        scenario.fixtures = serde_json::from_str(
+            r#"[{"pos": 100.0, "type": "stop_sign"}, {"pos": 300.0, "type": "on_ramp"},
+            {"pos": 400.0, "type": "actuated_light", "min_green": 10.0, "max_green": 40.0, "gap": 3.0, "red": 30.0, "detector": 50.0}]"#,
+        )
+        .unwrap();
+        assert!(scenario.validate().is_ok());
+        assert_eq!(scenario.simulation().unwrap().road().fixtures().len(), 3);
+        scenario.inflows[0].ramp = Some(0);
+        match scenario.validate() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "inflows[0].ramp"),
+            e => panic!("unexpected result {:?}", e),
+        }
+        scenario.inflows[0].ramp = Some(1);
+        scenario.inflows[0].destination = Some(1);
+        match scenario.validate() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "inflows[0].destination"),
+            e => panic!("unexpected result {:?}", e),
+        }
+        scenario.inflows[0].destination = None;
+        scenario.fixtures[0].kind = FixtureKind::TrafficLight { cycle: 60f64, green: 90f64, offset: 0f64 };
+        match scenario.validate() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "fixtures[0]"),
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        let mut scenario = Scenario::from_json(SCENARIO).unwrap();
+        scenario.cars.clear();
+        scenario.mixes.push(DriverMix {
+            density: 0.5,
+            vel: 0f64,
+            classes: vec![DriverClass {
+                name: "truck".to_string(),
+                share: 1f64,
+                size: Distribution::Uniform { min: 2f64, max: 4f64 },
+                drift: Distribution::Fixed { value: 1f64 },
+                behavior: Distribution::Fixed { value: 0f64 },
+                own_max_speed: Distribution::Fixed { value: 10f64 },
+                model: None,
+                noise: None,
+                vehicle: None,
+                anticipation: None,
+            }],
+        });
+        match scenario.validate() {
+            Err(ScenarioError::Invalid { field, .. }) => assert_eq!(field, "mixes[0]"),
+            e => panic!("unexpected result {:?}", e),
+        }
+        scenario.mixes[0].density = 0.1;
+        assert!(scenario.validate().is_ok());
+        assert_eq!(scenario.build_cars().unwrap().len(), 100);
+
+        let err = Scenario::from_toml(&SCENARIO_TOML.replace("dt = 0.1", "dt = \"fast\"")).unwrap_err();
+        match err {
+            ScenarioError::Parse { field, .. } => assert_eq!(field, "integrator.dt"),
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Actuated {
+    // Green lasts at least min_green, then goes on while the detector keeps calling, up to max_green
+    pub min_green: f64,
+    pub max_green: f64,
+    // Longest time without a call before the green ends
+    pub gap: f64,
+    // Red given to the crossing approaches
+    pub red: f64,
+    // Distance of the loop detector ahead of the stop line
+    pub detector: f64,
+}
+
+impl Actuated {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("min_green", self.min_green),
+            ("gap", self.gap),
+            ("red", self.red),
+            ("detector", self.detector),
+        ] {
+            if value.is_nan() || value <= 0f64 {
+                return Err(format!("{} {} should be positive", name, value));
+            }
+        }
+        if self.max_green.is_nan() || self.max_green < self.min_green {
+            return Err(format!("max_green {} is below min_green {}", self.max_green, self.min_green));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Signal {
+    green: bool,
+    // Start of the current phase, and last time a car went over the detector
+    since: f64,
+    last_call: Option<f64>,
+}
+
+impl Default for Signal {
+    fn default() -> Self {
+        Self { green: true, since: 0f64, last_call: None }
+    }
+}
+
+impl Signal {
+    pub fn is_green(&self) -> bool {
+        self.green
+    }
+
+    pub fn since(&self) -> f64 {
+        self.since
+    }
+
+    pub fn update(&mut self, plan: &Actuated, time: f64, called: bool) {
+        // The green gaps out once the calls stop after min_green, or maxes out
+        if called {
+            self.last_call = Some(time);
+        }
+        let elapsed = time - self.since;
+        let switch = if self.green {
+            let idle = time - self.last_call.map_or(self.since, |t| t.max(self.since));
+            elapsed >= plan.max_green || (elapsed >= plan.min_green && idle >= plan.gap)
+        } else {
+            elapsed >= plan.red
+        };
+        if switch {
+            self.green = !self.green;
+            self.since = time;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_actuated_signal() {
+        let plan = Actuated { min_green: 10f64, max_green: 30f64, gap: 3f64, red: 20f64, detector: 30f64 };
+        let run = |signal: &mut Signal, from: usize, to: usize, called: bool| {
+            for t in from..to {
+                signal.update(&plan, t as f64, called);
+            }
+        };
+
+        // Without any call the green ends as soon as min_green is over
+        let mut signal = Signal::default();
+        run(&mut signal, 0, 11, false);
+        assert!(!signal.is_green());
+        assert_eq!(signal.since(), 10f64);
+        run(&mut signal, 11, 31, false);
+        assert!(signal.is_green());
+
+        // Steady calls hold the green up to max_green, it gaps out once they stop
+        let mut signal = Signal::default();
+        run(&mut signal, 0, 31, true);
+        assert_eq!((signal.is_green(), signal.since()), (false, 30f64));
+        run(&mut signal, 31, 51, true);
+        run(&mut signal, 51, 60, true);
+        run(&mut signal, 60, 64, false);
+        assert_eq!((signal.is_green(), signal.since()), (false, 62f64));
+        assert!(plan.validate().is_ok());
+        assert!(Actuated { max_green: 5f64, ..plan }.validate().is_err());
+    }
+}
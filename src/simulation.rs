@@ -0,0 +1,1633 @@
+use crate::anticipation;
+use crate::collision::{CollisionPolicy, Crash};
+use crate::inflow::{Exit, Inflow};
+use crate::infrastructure::{self, Ahead, FixtureKind};
+use crate::integrator::{self, Adaptive, IntegratorKind, State};
+use crate::lane::{self, LaneChange, LaneChangeRule, LaneIndex, Neighbors};
+use crate::limits::{EmergencyBraking, Limits};
+use crate::measure::LoopDetector;
+use crate::model::{model_of, Model};
+use crate::recorder::Observer;
+use crate::scenario::positive;
+use crate::{CamId, Car, CarId, FixtureId, Road, SectionRecord, SpeedCam, TrafficItem, TrafficList, ViolationLog};
+use moldybrody::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+// Everything a run depends on is owned data, so a simulation can be cloned, sent to another
+// thread or checkpointed and resumed from where it stood, random state included.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "SimulationData")]
+pub struct Simulation {
+    road: Road,
+    // False for an open road, where cars enter through inflows and leave at the end
+    periodic: bool,
+    inflows: Vec<Inflow>,
+    exits: Vec<Exit>,
+    // Cars that went past the off ramp they were bound for on another lane than the first
+    missed_exits: usize,
+    brakings: Vec<EmergencyBraking>,
+    collisions: CollisionPolicy,
+    crashes: Vec<Crash>,
+    // Pairs of cars overlapping since their crash, so that it is recorded once
+    colliding: BTreeSet<(CarId, CarId)>,
+    aborted: bool,
+    integrator: IntegratorKind,
+    adaptive: Adaptive,
+    // Last substep of the adaptive integrator, and how many it took to cover the last step
+    substep: f64,
+    substeps: usize,
+    length: f64,
+    lanes: usize,
+    lane_change: Option<LaneChange>,
+    model: Model,
+    // Limits of the cars that do not carry their own, None leaving them unbounded
+    limits: Option<Limits>,
+    // Loop detectors calling the actuated lights, by light
+    detectors: BTreeMap<FixtureId, LoopDetector>,
+    seed: u64,
+    rng: Pcg64,
+    dt: f64,
+    time: f64,
+}
+
+// Serialized form of a Simulation, which goes through the checks of its builders when read back
+#[derive(Deserialize)]
+struct SimulationData {
+    road: Road,
+    periodic: bool,
+    inflows: Vec<Inflow>,
+    exits: Vec<Exit>,
+    missed_exits: usize,
+    brakings: Vec<EmergencyBraking>,
+    collisions: CollisionPolicy,
+    crashes: Vec<Crash>,
+    colliding: BTreeSet<(CarId, CarId)>,
+    aborted: bool,
+    integrator: IntegratorKind,
+    adaptive: Adaptive,
+    substep: f64,
+    substeps: usize,
+    length: f64,
+    lanes: usize,
+    lane_change: Option<LaneChange>,
+    model: Model,
+    limits: Option<Limits>,
+    detectors: BTreeMap<FixtureId, LoopDetector>,
+    // Loop detectors calling the actuated lights, by light
+    detectors: BTreeMap<FixtureId, LoopDetector>,
+    seed: u64,
+    rng: Pcg64,
+    dt: f64,
+    time: f64,
+}
+
+impl TryFrom<SimulationData> for Simulation {
+    type Error = String;
+
+    fn try_from(data: SimulationData) -> Result<Self, String> {
+        let sim = Self {
+            road: data.road,
+            periodic: data.periodic,
+            inflows: data.inflows,
+            exits: data.exits,
+            missed_exits: data.missed_exits,
+            brakings: data.brakings,
+            collisions: data.collisions,
+            crashes: data.crashes,
+            colliding: data.colliding,
+            aborted: data.aborted,
+            integrator: data.integrator,
+            adaptive: data.adaptive,
+            substep: data.substep,
+            substeps: data.substeps,
+            length: data.length,
+            lanes: data.lanes,
+            lane_change: data.lane_change,
+            model: data.model,
+            limits: data.limits,
+            detectors: data.detectors,
+            seed: data.seed,
+            rng: data.rng,
+            dt: data.dt,
+            time: data.time,
+        };
+        sim.validate()?;
+        Ok(sim)
+    }
+}
+
+impl Simulation {
+    pub fn new(road: Road, length: f64, dt: f64) -> Self {
+        if !positive(dt) {
+            panic!("Invalid time step : dt {} should be positive", dt);
+        }
+        // The road has at least the lanes its cars are on, Simulation::with_lanes sets the actual count
+        let lanes = road.traffic.cars().map(|c| c.lane + 1).max().unwrap_or(1);
+
+        Self {
+            road,
+            periodic: true,
+            inflows: vec![],
+            exits: vec![],
+            missed_exits: 0,
+            brakings: vec![],
+            collisions: CollisionPolicy::Record,
+            crashes: vec![],
+            colliding: BTreeSet::new(),
+            aborted: false,
+            integrator: IntegratorKind::Euler,
+            adaptive: Adaptive::default(),
+            substep: dt,
+            substeps: 1,
+            length,
+            lanes,
+            lane_change: None,
+            model: Model::LennardJones,
+            limits: None,
+            detectors: BTreeMap::new(),
+            seed: 0,
+            rng: Pcg64::seed_from_u64(0),
+            dt,
+            time: 0f64,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        // Everything the builders check, for a simulation read back from a checkpoint
+        if !positive(self.dt) {
+            return Err(format!("time step : dt {} should be positive", self.dt));
+        }
+        self.check_lanes(self.lanes).map_err(|message| format!("number of lanes : {}", message))?;
+        for inflow in self.inflows.iter() {
+            self.check_inflow(inflow).map_err(|message| format!("inflow : {}", message))?;
+        }
+        self.model.validate().map_err(|message| format!("model : {}", message))?;
+        if let Some(limits) = &self.limits {
+            limits.validate().map_err(|message| format!("limits : {}", message))?;
+        }
+        self.adaptive.validate().map_err(|message| format!("adaptive integrator : {}", message))
+    }
+
+    fn check_lanes(&self, lanes: usize) -> Result<(), String> {
+        if lanes == 0 {
+            return Err("road should have at least one lane".to_string());
+        }
+        // Lane `lanes` of the index is kept for the cars merging from on ramps
+        if let Some(c) = self.road.traffic.cars().find(|c| c.lane >= lanes) {
+            return Err(format!("car on lane {} of a road with {} lanes", c.lane, lanes));
+        }
+        if let Some(inflow) = self.inflows.iter().find(|inflow| inflow.lane() >= lanes) {
+            return Err(format!("inflow on lane {} of a road with {} lanes", inflow.lane(), lanes));
+        }
+        Ok(())
+    }
+
+    fn check_inflow(&self, inflow: &Inflow) -> Result<(), String> {
+        if self.is_periodic() && inflow.ramp().is_none() {
+            return Err("cars only enter a ring through an on ramp".to_string());
+        }
+        if inflow.lane() >= self.lanes {
+            return Err(format!("lane {} of a road with {} lanes", inflow.lane(), self.lanes));
+        }
+        if let Some(ramp) = inflow.ramp() {
+            if !matches!(self.road.fixtures.get(ramp.0), Some(f) if matches!(f.kind, FixtureKind::OnRamp { .. })) {
+                return Err(format!("fixture {} is not an on ramp", ramp.0));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn with_lanes(mut self, lanes: usize) -> Self {
+        if let Err(message) = self.check_lanes(lanes) {
+            panic!("Invalid number of lanes : {}", message);
+        }
+        self.lanes = lanes;
+        self
+    }
+
+    pub fn with_open_boundary(mut self) -> Self {
+        self.periodic = false;
+        self
+    }
+
+    pub fn with_inflow(mut self, inflow: Inflow) -> Self {
+        if let Err(message) = self.check_inflow(&inflow) {
+            panic!("Invalid inflow : {}", message);
+        }
+        self.inflows.push(inflow);
+        self
+    }
+
+    pub fn is_periodic(&self) -> bool {
+        self.periodic
+    }
+
+    pub fn inflows(&self) -> &[Inflow] {
+        &self.inflows
+    }
+
+    pub fn exits(&self) -> &[Exit] {
+        &self.exits
+    }
+
+    pub fn missed_exits(&self) -> usize {
+        self.missed_exits
+    }
+
+    pub fn emergency_brakings(&self) -> &[EmergencyBraking] {
+        &self.brakings
+    }
+
+    pub fn with_collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.collisions = policy;
+        self
+    }
+
+    pub fn collision_policy(&self) -> CollisionPolicy {
+        self.collisions
+    }
+
+    pub fn crashes(&self) -> &[Crash] {
+        &self.crashes
+    }
+
+    pub fn with_integrator(mut self, integrator: IntegratorKind) -> Self {
+        self.integrator = integrator;
+        self
+    }
+
+    pub fn with_adaptive(mut self, adaptive: Adaptive) -> Self {
+        if let Err(message) = adaptive.validate() {
+            panic!("Invalid adaptive integrator : {}", message);
+        }
+        self.adaptive = adaptive;
+        self
+    }
+
+    pub fn integrator(&self) -> IntegratorKind {
+        self.integrator
+    }
+
+    pub fn substeps(&self) -> usize {
+        self.substeps
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        // Only under CollisionPolicy::Abort, the last crash tells why
+        self.aborted
+    }
+
+    pub fn with_lane_change<R: Into<LaneChange>>(mut self, rule: R) -> Self {
+        self.lane_change = Some(rule.into());
+        self
+    }
+
+    pub fn with_model(self, model: Model) -> Self {
+        match self.try_with_model(model) {
+            Ok(sim) => sim,
+            Err(message) => panic!("Invalid model : {}", message),
+        }
+    }
+
+    pub fn try_with_model(mut self, model: Model) -> Result<Self, String> {
+        // Used by every car that does not carry a model of its own
+        model.validate()?;
+        self.model = model;
+        Ok(self)
+    }
+
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        if let Err(message) = limits.validate() {
+            panic!("Invalid limits : {}", message);
+        }
+        self.limits = Some(limits);
+        self
+    }
+
+    pub fn limits(&self) -> Option<&Limits> {
+        self.limits.as_ref()
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        // Every random draw of the run comes from this seed
+        self.seed = seed;
+        self.rng = Pcg64::seed_from_u64(seed);
+        self
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn dt(&self) -> f64 {
+        self.dt
+    }
+
+    pub fn length(&self) -> f64 {
+        self.length
+    }
+
+    pub fn lanes(&self) -> usize {
+        self.lanes
+    }
+
+    pub fn road(&self) -> &Road {
+        &self.road
+    }
+
+    pub fn traffic(&self) -> &TrafficList {
+        &self.road.traffic
+    }
+
+    pub fn sections(&self) -> &[SectionRecord] {
+        self.road.traffic.sections()
+    }
+
+    pub fn cams(&self) -> &[SpeedCam] {
+        self.road.cams()
+    }
+
+    pub fn violations(&self) -> ViolationLog {
+        self.road.violations()
+    }
+
+    pub fn cars(&self) -> impl Iterator<Item = &Car> {
+        self.road.traffic.cars()
+    }
+
+    pub fn forces(&self) -> Vec<Cartessian1D<f64>> {
+        // Cars are visited in the order of the list, so a car lying between
+        // the opening and the closing flag of a camera is inside its zone.
+        // Every car only feels its leader and follower on the same lane.
+        let index = LaneIndex::new(&self.road.traffic, self.lanes);
+        let periodic = self.is_periodic();
+        let cams = self.road.cams();
+        let mut active: Vec<CamId> = vec![];
+        let mut forces = Vec::with_capacity(self.road.traffic.len());
+        let views = anticipation::views(&self.road.traffic, self.length, periodic);
+        let fixtures = infrastructure::ahead(&self.road.traffic, self.length, periodic);
+
+        for (i, item) in self.road.traffic.iter().enumerate() {
+            match item {
+                TrafficItem::Flag(f) => {
+                    if f.status {
+                        active.push(f.cam);
+                    } else {
+                        active.retain(|&cam| cam != f.cam);
+                    }
+                }
+                TrafficItem::Fixture(_) => {}
+                TrafficItem::Car(c) => {
+                    let model = model_of(c, &self.model);
+                    let mut acc = match self.car_at(index.leader(i, periodic)) {
+                        Some(l) => model.acceleration(c, l, self.distance(c, l)),
+                        None => model.free_acceleration(c),
+                    };
+                    if let Some(distance) = self.stop_line(&fixtures, i, c, c.lane) {
+                        acc = acc.min(model.acceleration(c, &infrastructure::stop_line(c.lane, c.pos[0] + distance), distance));
+                    }
+                    if c.lane > 0 && self.diverging(c) {
+                        // Drivers bound for an off ramp drop behind the car ahead on the next lane to get into its gap
+                        if let Some(l) = self.car_at(index.around(c.lane - 1, i, periodic).0) {
+                            acc = acc.min(model.acceleration(c, l, self.distance(c, l)));
+                        }
+                    }
+                    if let Some(f) = self.car_at(index.follower(i, periodic)) {
+                        acc += model.push(c, f, self.distance(f, c));
+                    }
+                    if let Some(anticipation) = &c.anticipation {
+                        acc = anticipation.acceleration(c, acc, &views[i], cams);
+                    }
+                    let mut force = Cartessian1D::new([acc * c.mass]);
+                    for cam in active.iter() {
+                        force += cams[cam.0].force_to(c);
+                    }
+                    forces.push(force);
+                }
+            }
+        }
+        forces
+    }
+
+    fn car_at(&self, i: Option<usize>) -> Option<&Car> {
+        match i.and_then(|i| self.road.traffic.get(i)) {
+            Some(TrafficItem::Car(c)) => Some(c),
+            _ => None,
+        }
+    }
+
+    fn distance(&self, rear: &Car, front: &Car) -> f64 {
+        // Distance from rear to front going forward, around the ring if there is one
+        self.distance_to(rear, front.pos[0])
+    }
+
+    fn distance_to(&self, car: &Car, pos: f64) -> f64 {
+        if self.is_periodic() {
+            (pos - car.pos[0]).rem_euclid(self.length)
+        } else {
+            pos - car.pos[0]
+        }
+    }
+
+    fn neighbors(&self, index: &LaneIndex, i: usize, lane: usize) -> Neighbors<'_> {
+        let periodic = self.is_periodic();
+        let (leader, follower) = index.around(lane, i, periodic);
+        Neighbors { leader: self.car_at(leader), follower: self.car_at(follower), ring: periodic.then_some(self.length) }
+    }
+
+    fn stop_line(&self, ahead: &Ahead, i: usize, car: &Car, lane: usize) -> Option<f64> {
+        // Distance from the item i to the nearest fixture ahead where `car` would have to stop on `lane`,
+        // or to the end of the acceleration lane it is on
+        if self.road.fixtures.is_empty() {
+            return None;
+        }
+        if let Some(end) = car.merging.and_then(|ramp| self.road.fixture(ramp).merge_end()) {
+            return Some(self.distance_to(car, end));
+        }
+        ahead
+            .of(i, car.pos[0])
+            .find(|&(id, _)| self.road.fixture(id).blocks(id, car, lane, self.time))
+            .map(|(_, distance)| distance)
+    }
+
+    fn nearer<'n>(&self, car: &Car, leader: Option<&'n Car>, stop: Option<&'n Car>) -> Option<&'n Car> {
+        // The leader or the stop line ahead of `car`, whichever comes first
+        match (leader, stop) {
+            (Some(l), Some(s)) if self.distance(car, l) < s.pos[0] - car.pos[0] => Some(l),
+            (_, Some(s)) => Some(s),
+            (l, None) => l,
+        }
+    }
+
+    pub fn change_lanes(&mut self) {
+        let rule = match &self.lane_change {
+            Some(rule) => rule,
+            None => return,
+        };
+
+        // Decisions are made one car at a time so that two cars never merge into the same gap
+        let mut index = LaneIndex::new(&self.road.traffic, self.lanes);
+        let fixtures = infrastructure::ahead(&self.road.traffic, self.length, self.is_periodic());
+        for i in 0..self.road.traffic.len() {
+            let (car, lane) = match self.road.traffic.get(i) {
+                Some(TrafficItem::Car(c)) if !c.blocked && c.merging.is_none() && !self.diverging(c) => (c, c.lane),
+                _ => continue,
+            };
+            // Stop lines act as standing leaders, lanes that ended are out of reach
+            let stop = |lane: usize| self.stop_line(&fixtures, i, car, lane).map(|d| infrastructure::stop_line(lane, car.pos[0] + d));
+            let current_stop = stop(lane);
+            let mut current = self.neighbors(&index, i, lane);
+            current.leader = self.nearer(car, current.leader, current_stop.as_ref());
+
+            let mut best: Option<(usize, f64)> = None;
+            let reachable = |l: &usize| *l < self.lanes && car.allows_lane(*l) && !self.road.fixtures.iter().any(|f| f.closes(*l, car.pos[0]));
+            let targets = [lane.checked_sub(1).filter(reachable), Some(lane + 1).filter(reachable)];
+            for target in targets.into_iter().flatten() {
+                let target_stop = stop(target);
+                let mut neighbors = self.neighbors(&index, i, target);
+                neighbors.leader = self.nearer(car, neighbors.leader, target_stop.as_ref());
+                if let Some(incentive) = rule.incentive(&self.model, car, current, neighbors) {
+                    if incentive > best.map_or(0f64, |(_, b)| b) {
+                        best = Some((target, incentive));
+                    }
+                }
+            }
+
+            if let Some((target, _)) = best {
+                if let Some(TrafficItem::Car(c)) = self.road.traffic.get_mut(i) {
+                    c.lane = target;
+                    index.move_car(i, target);
+                }
+            }
+        }
+    }
+
+    fn diverging(&self, car: &Car) -> bool {
+        // Whether `car` is close enough to the off ramp it is bound for to keep to the first lane
+        let ramp = match car.destination {
+            Some(ramp) => self.road.fixture(ramp),
+            None => return false,
+        };
+        matches!(ramp.kind, FixtureKind::OffRamp { diverge, .. } if (0f64..diverge).contains(&self.distance_to(car, ramp.pos)))
+    }
+
+    fn lane_ends(&self, ahead: &Ahead, i: usize, car: &Car) -> bool {
+        // Whether the next place `car` has to stop at on its lane is the end of that lane, close by
+        ahead
+            .of(i, car.pos[0])
+            .find(|&(id, _)| self.road.fixture(id).blocks(id, car, car.lane, self.time))
+            .is_some_and(|(id, distance)| matches!(self.road.fixture(id).kind, FixtureKind::LaneDrop { .. }) && distance < infrastructure::DROP_RANGE)
+    }
+
+    fn use_ramps(&mut self) {
+        // Cars on an acceleration lane merge into the first lane, and those bound for an off ramp close by
+        // or driving on a lane about to end move towards it, as soon as the gaps on the next lane are accepted
+        if self.road.fixtures.is_empty() {
+            return;
+        }
+        let mut index = LaneIndex::new(&self.road.traffic, self.lanes);
+        let ahead = infrastructure::ahead(&self.road.traffic, self.length, self.is_periodic());
+        for i in 0..self.road.traffic.len() {
+            let (car, target) = match self.road.traffic.get(i) {
+                Some(TrafficItem::Car(c)) if c.blocked => continue,
+                Some(TrafficItem::Car(c)) if c.merging.is_some() => (c, 0),
+                Some(TrafficItem::Car(c)) if c.lane > 0 && (self.diverging(c) || self.lane_ends(&ahead, i, c)) => (c, c.lane - 1),
+                _ => continue,
+            };
+            if !car.allows_lane(target) || self.road.fixtures.iter().any(|f| f.closes(target, car.pos[0])) {
+                continue;
+            }
+            let (leader, follower) = index.around(target, i, self.is_periodic());
+            let (leader, follower) = (self.car_at(leader), self.car_at(follower));
+            if !lane::gap_accepted(car, leader.map(|l| self.distance(car, l)), follower.map(|f| (f, self.distance(f, car)))) {
+                continue;
+            }
+            if let Some(TrafficItem::Car(c)) = self.road.traffic.get_mut(i) {
+                c.merging = None;
+                c.lane = target;
+                index.move_car(i, target);
+            }
+        }
+    }
+
+    fn update_sections(&mut self) {
+        // Drivers inside a section control adapt their target to the remaining time budget
+        let dt = self.dt;
+        let cams = &self.road.cams;
+        // Every section control keeps its own state, so overlapping sections do not cut each other short
+        let mut active: Vec<CamId> = vec![];
+        for item in self.road.traffic.iter_mut() {
+            match item {
+                TrafficItem::Flag(f) if cams[f.cam.0].check_average => {
+                    if f.status {
+                        active.push(f.cam);
+                    } else {
+                        active.retain(|&cam| cam != f.cam);
+                    }
+                }
+                TrafficItem::Flag(_) | TrafficItem::Fixture(_) => {}
+                TrafficItem::Car(c) => {
+                    let mut target: Option<f64> = None;
+                    for cam in active.iter() {
+                        if let Some(elapsed) = c.section_times.get_mut(cam) {
+                            *elapsed += dt;
+                            let elapsed = *elapsed;
+                            let speed = cams[cam.0].section_speed(c, elapsed);
+                            target = Some(target.map_or(speed, |t| t.min(speed)));
+                        }
+                    }
+                    if let Some(target) = target {
+                        c.max_speed = c.own_max_speed.min(target);
+                    }
+                }
+            }
+        }
+    }
+
+    fn clear_fixtures(&mut self) {
+        // Every car decides whether it may go through the next light or stop sign
+        if self.road.fixtures.is_empty() {
+            return;
+        }
+        let ahead = infrastructure::ahead(&self.road.traffic, self.length, self.is_periodic());
+        let next: Vec<_> = (0..self.road.traffic.len())
+            .filter_map(|i| match self.road.traffic.get(i) {
+                Some(TrafficItem::Car(c)) => ahead
+                    .of(i, c.pos[0])
+                    .find(|&(id, _)| self.road.fixture(id).is_light() || self.road.fixture(id).kind == FixtureKind::StopSign)
+                    .map(|(id, distance)| (i, id, distance)),
+                _ => None,
+            })
+            .collect();
+        for (i, id, distance) in next {
+            if let Some(TrafficItem::Car(c)) = self.road.traffic.items.get_mut(i) {
+                self.road.fixtures[id.0].clear(id, c, distance, self.time);
+            }
+        }
+    }
+
+    fn actuate_signals(&mut self) {
+        // Actuated lights are called by the cars going over their loop detector, `detector` ahead of the
+        // stop line on every lane. Detectors only report passages, their windows never close.
+        let mut detectors = std::mem::take(&mut self.detectors);
+        let mut calls = vec![false; self.road.fixtures.len()];
+        for (k, fixture) in self.road.fixtures.iter().enumerate() {
+            let FixtureKind::ActuatedLight(plan) = fixture.kind else { continue };
+            let pos = if self.is_periodic() { (fixture.pos - plan.detector).rem_euclid(self.length) } else { (fixture.pos - plan.detector).max(0f64) };
+            let detector = detectors.entry(FixtureId(k)).or_insert_with(|| LoopDetector::new(pos, f64::MAX));
+            detector.observe(self);
+            calls[k] = !detector.take_passages().is_empty();
+        }
+        self.detectors = detectors;
+        for (fixture, called) in self.road.fixtures.iter_mut().zip(calls) {
+            fixture.actuate(self.time, called);
+        }
+    }
+
+    fn notice_cameras(&mut self) {
+        // A driver coming in sight of a camera notices it or not once and for all
+        let views = anticipation::views(&self.road.traffic, self.length, self.is_periodic());
+        let (cams, rng) = (&self.road.cams, &mut self.rng);
+        for (item, view) in self.road.traffic.iter_mut().zip(views) {
+            let (c, (id, distance)) = match (item, view.ahead) {
+                (TrafficItem::Car(c), Some(ahead)) => (c, ahead),
+                _ => continue,
+            };
+            let awareness = match &c.anticipation {
+                Some(anticipation) => anticipation.awareness,
+                None => continue,
+            };
+            let seen = matches!(c.noticed, Some((seen, _)) if seen == id);
+            if distance < cams[id.0].sight_distance && !seen {
+                c.noticed = Some((id, rng.gen::<f64>() < awareness));
+            }
+        }
+    }
+
+    fn add_noise(&mut self, forces: &mut [Cartessian1D<f64>]) -> Vec<f64> {
+        // Noise is drawn once per step, the accelerations are returned for the later stages of the integrators
+        let dt = self.dt;
+        let rng = &mut self.rng;
+        let cars = self.road.traffic.iter_mut().filter_map(|item| match item {
+            TrafficItem::Car(c) => Some(c),
+            _ => None,
+        });
+        let mut accelerations = Vec::with_capacity(forces.len());
+        for (c, force) in cars.zip(forces.iter_mut()) {
+            let acc = match &c.noise {
+                Some(noise) => noise.acceleration(&mut c.noise_state, c.vel[0], dt, rng),
+                None => 0f64,
+            };
+            *force += Cartessian1D::new([c.mass * acc]);
+            accelerations.push(acc);
+        }
+        accelerations
+    }
+
+    fn set_state(&mut self, x: &[f64], v: &[f64]) {
+        let cars = self.road.traffic.iter_mut().filter_map(|item| match item {
+            TrafficItem::Car(c) => Some(c),
+            _ => None,
+        });
+        for ((c, x), v) in cars.zip(x).zip(v) {
+            c.pos[0] = *x;
+            c.vel[0] = *v;
+        }
+    }
+
+    fn stage(&mut self, x: &[f64], v: &[f64], noise: &[f64], h: f64) -> Vec<f64> {
+        // Accelerations with the cars put at an intermediate state of an integrator
+        self.set_state(x, v);
+        let forces = self.forces();
+        let default = self.limits;
+        self.road.traffic
+            .cars()
+            .zip(forces.iter())
+            .zip(noise)
+            .map(|((c, f), n)| {
+                let acc = f[0] / c.mass + n;
+                match c.limits.or(default) {
+                    _ if c.blocked => 0f64,
+                    Some(limits) => limits.apply(c.vel[0], acc, h).0,
+                    None => acc,
+                }
+            })
+            .collect()
+    }
+
+    fn contact_time(&self, pairs: &[(usize, usize, f64)], x: &[f64], v: &[f64]) -> f64 {
+        // Shortest time any car needs to close the gap to its leader from the state (x, v) of the cars.
+        // Pairs hold item indices, the state is ordered like the cars alone.
+        let mut ordinals = vec![None; self.road.traffic.len()];
+        let mut k = 0;
+        for (i, item) in self.road.traffic.iter().enumerate() {
+            if let TrafficItem::Car(_) = item {
+                ordinals[i] = Some(k);
+                k += 1;
+            }
+        }
+        pairs
+            .iter()
+            .filter_map(|&(i, j, _)| {
+                let (rear, front) = (self.car_at(Some(i))?, self.car_at(Some(j))?);
+                let (a, b) = (ordinals[i]?, ordinals[j]?);
+                let closing = v[a] - v[b];
+                let distance = if self.is_periodic() { (x[b] - x[a]).rem_euclid(self.length) } else { x[b] - x[a] };
+                let gap = distance - 0.5 * (rear.size + front.size);
+                (closing > 0f64).then(|| gap.max(0f64) / closing)
+            })
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    fn adaptive_step(&mut self, state: State, a: Vec<f64>, noise: &[f64], pairs: &[(usize, usize, f64)]) -> State {
+        // Substeps never cover more than a fraction of the time to the next contact, from the state they start at
+        let (dt, params) = (self.dt, self.adaptive);
+        let (mut x, mut v, mut a) = (state.0, state.1, a);
+        let mut h = self.substep.min(params.contact_fraction * self.contact_time(pairs, &x, &v));
+        let mut t = 0f64;
+        self.substeps = 0;
+        while dt - t > 1e-9 * dt {
+            let step = h.max(params.min_dt).min(dt - t);
+            let ((x1, v1), error) = integrator::heun(&x, &v, &a, step, |x, v| self.stage(x, v, noise, step));
+            if error > params.tolerance && step > params.min_dt {
+                h = params.next_dt(step, error);
+                continue;
+            }
+            t += step;
+            x = x1;
+            v = v1;
+            self.substeps += 1;
+            h = params.next_dt(step, error).min(dt).min(params.contact_fraction * self.contact_time(pairs, &x, &v));
+            if dt - t > 1e-9 * dt {
+                a = self.stage(&x, &v, noise, h);
+            }
+        }
+        self.substep = h;
+        (x, v)
+    }
+
+    fn integrate(&mut self, forces: &[Cartessian1D<f64>], noise: &[f64], pairs: &[(usize, usize, f64)]) -> (Vec<CarId>, Vec<CarId>) {
+        // The accelerations of the first stage are the limited forces
+        let dt = self.dt;
+        let (x, v): State = self.road.traffic.cars().map(|c| (c.pos[0], c.vel[0])).unzip();
+        let a: Vec<f64> = self.road.traffic.cars().zip(forces).map(|(c, f)| if c.blocked { 0f64 } else { f[0] / c.mass }).collect();
+        let kind = self.integrator;
+        let (x1, v1) = match kind {
+            IntegratorKind::Euler => integrator::euler(&x, &v, &a, dt),
+            IntegratorKind::Ballistic => integrator::ballistic(&x, &v, &a, dt),
+            IntegratorKind::Verlet => integrator::verlet(&x, &v, &a, dt, |x, v| self.stage(x, v, noise, dt)),
+            IntegratorKind::Rk4 => integrator::rk4(&x, &v, &a, dt, |x, v| self.stage(x, v, noise, dt)),
+            IntegratorKind::Adaptive => self.adaptive_step((x.clone(), v.clone()), a, noise, pairs),
+        };
+
+        // On a ring, cars which went over the end and cars which rolled back over the start are told apart
+        if !self.periodic {
+            self.set_state(&x1, &v1);
+            return (vec![], vec![]);
+        }
+        let crossed = |keep: fn(f64, f64) -> bool| -> Vec<CarId> {
+            self.road.traffic.cars().zip(&x1).filter(|(_, x)| keep(**x, self.length)).map(|(c, _)| c.listed_id()).collect()
+        };
+        let (wrapped, reversed) = (crossed(|x, length| x >= length), crossed(|x, _| x < 0f64));
+        let x1: Vec<f64> = x1.iter().map(|x| x.rem_euclid(self.length)).collect();
+        self.set_state(&x1, &v1);
+        (wrapped, reversed)
+    }
+
+    fn limit_forces(&mut self, forces: &mut [Cartessian1D<f64>]) {
+        // Only the onset of an emergency braking is recorded, not every step of it
+        let (dt, time, default) = (self.dt, self.time, self.limits);
+        let brakings = &mut self.brakings;
+        let cars = self.road.traffic.iter_mut().filter_map(|item| match item {
+            TrafficItem::Car(c) => Some(c),
+            _ => None,
+        });
+        for (c, force) in cars.zip(forces.iter_mut()) {
+            if c.blocked {
+                continue;
+            }
+            let Some(limits) = c.limits.or(default) else { continue };
+            let demanded = force[0] / c.mass;
+            let (applied, emergency) = limits.apply(c.vel[0], demanded, dt);
+            if emergency && !c.braking {
+                brakings.push(EmergencyBraking { car: c.listed_id(), time, pos: c.pos[0], speed: c.vel[0], demanded, applied });
+            }
+            c.braking = emergency;
+            *force = Cartessian1D::new([c.mass * applied]);
+        }
+    }
+
+    fn leader_pairs(&self) -> Vec<(usize, usize, f64)> {
+        // Index of every car with the one of the car ahead of it on its lane and the distance between them
+        let index = LaneIndex::new(&self.road.traffic, self.lanes);
+        let periodic = self.is_periodic();
+        (0..self.road.traffic.len())
+            .filter_map(|i| {
+                let j = index.leader(i, periodic)?;
+                Some((i, j, self.distance(self.car_at(Some(i))?, self.car_at(Some(j))?)))
+            })
+            .collect()
+    }
+
+    fn detect_collisions(&mut self, pairs: &[(usize, usize, f64)]) -> Vec<CarId> {
+        // Cars collide when they overlap, or when the rear one went through the front one within the step.
+        // Items have moved but are not reordered yet, so the indices of the pairs still hold.
+        let mut crashed = vec![];
+        for &(i, j, before) in pairs {
+            let (rear, front) = match (self.car_at(Some(i)), self.car_at(Some(j))) {
+                (Some(rear), Some(front)) => (rear, front),
+                _ => continue,
+            };
+            let mut after = self.distance(rear, front);
+            if self.is_periodic() && after - before > 0.5 * self.length {
+                after -= self.length;
+            }
+            let crash = Crash {
+                time: self.time,
+                lane: rear.lane,
+                pos: front.pos[0],
+                rear: rear.listed_id(),
+                front: front.listed_id(),
+                rear_speed: rear.vel[0],
+                front_speed: front.vel[0],
+                overlap: 0.5 * (rear.size + front.size) - after,
+            };
+            let pair = (crash.rear, crash.front);
+            if crash.overlap <= 0f64 {
+                self.colliding.remove(&pair);
+            } else if self.colliding.insert(pair) {
+                crashed.extend([pair.0, pair.1]);
+                self.crashes.push(crash);
+            }
+        }
+        crashed
+    }
+
+    fn handle_crashes(&mut self, crashed: Vec<CarId>) {
+        match self.collisions {
+            CollisionPolicy::Record => {}
+            CollisionPolicy::Remove => {
+                for &id in crashed.iter() {
+                    self.road.traffic.remove_car(id);
+                }
+                self.colliding.retain(|(rear, front)| !crashed.contains(rear) && !crashed.contains(front));
+            }
+            CollisionPolicy::Block => {
+                for id in crashed {
+                    if let Some(TrafficItem::Car(c)) = self.road.traffic.position(id).and_then(|i| self.road.traffic.get_mut(i)) {
+                        c.blocked = true;
+                        c.vel[0] = 0f64;
+                    }
+                }
+            }
+            CollisionPolicy::Abort => self.aborted = true,
+        }
+    }
+
+    fn remove_exits(&mut self) {
+        // Cars past the end are the last items once the list is sorted
+        loop {
+            let last = self.road.traffic.len().checked_sub(1).and_then(|i| self.road.traffic.get(i));
+            let id = match last {
+                Some(TrafficItem::Car(c)) if c.pos[0] >= self.length => c.listed_id(),
+                _ => break,
+            };
+            self.leave(id, None);
+        }
+    }
+
+    fn leave(&mut self, id: CarId, ramp: Option<FixtureId>) {
+        if let Some(car) = self.road.traffic.remove_car(id) {
+            self.exits.push(Exit {
+                car: id,
+                lane: car.lane,
+                entry_time: car.entry_time,
+                exit_time: self.time,
+                speed: car.vel[0],
+                ramp,
+            });
+        }
+    }
+
+    fn take_off_ramps(&mut self) {
+        // Cars bound for an off ramp take it from the first lane, those without a destination with the share of the ramp
+        for (id, ramp) in self.road.take_departures() {
+            let share = match self.road.fixture(ramp).kind {
+                FixtureKind::OffRamp { share, .. } => share,
+                _ => continue,
+            };
+            let takes = match self.road.traffic.car(id).map(|c| (c.destination, c.lane)) {
+                Some((Some(destination), lane)) if destination == ramp && lane > 0 => {
+                    // The exit is missed, the car drives on with no destination left
+                    self.missed_exits += 1;
+                    self.road.traffic.car_mut(id).unwrap().destination = None;
+                    false
+                }
+                Some((Some(destination), _)) => destination == ramp,
+                Some((None, lane)) => lane == 0 && self.rng.gen::<f64>() < share,
+                None => false,
+            };
+            if takes {
+                self.leave(id, Some(ramp));
+            }
+        }
+    }
+
+    fn admit(&mut self) {
+        // Queued cars enter at the start or at their ramp, once the cars around are a safe distance away.
+        // Ramps with an acceleration lane take them onto it.
+        for inflow in self.inflows.iter_mut() {
+            inflow.arrive(self.time, &mut self.rng);
+            let entry = inflow.ramp().map_or(0f64, |ramp| self.road.fixture(ramp).pos);
+            let merging = inflow.ramp().filter(|&ramp| self.road.fixture(ramp).merge_end().is_some());
+            while let Some(mut car) = inflow.next_car(self.time, &mut self.rng) {
+                car.pos[0] = entry;
+                if merging.is_some() {
+                    car.lane = 0;
+                    car.merging = merging;
+                }
+                let on_lane = |c: &&Car| c.lane == car.lane && c.merging == car.merging;
+                let follower = self.road.traffic.cars().filter(on_lane).filter(|c| c.pos[0] < entry).last();
+                let leader = self.road.traffic.cars().filter(on_lane).find(|c| c.pos[0] >= entry);
+                if !lane::gap_accepted(&car, leader.map(|l| l.pos[0] - entry), follower.map(|f| (f, entry - f.pos[0]))) {
+                    break;
+                }
+                self.road.insert_car(car);
+                inflow.entered();
+            }
+        }
+    }
+
+    pub fn step(&mut self) {
+        if self.aborted {
+            return;
+        }
+        self.actuate_signals();
+        self.clear_fixtures();
+        self.notice_cameras();
+        let pairs = self.leader_pairs();
+        let mut forces = self.forces();
+        let noise = self.add_noise(&mut forces);
+        self.limit_forces(&mut forces);
+        let (wrapped, reversed) = self.integrate(&forces, &noise, &pairs);
+
+        self.time += self.dt;
+        let crashed = self.detect_collisions(&pairs);
+        if self.is_periodic() {
+            self.road.wrap_around(&wrapped, self.length, self.time);
+            self.road.wrap_backward(&reversed);
+        }
+        self.road.reorder(self.time);
+        if !crashed.is_empty() {
+            self.handle_crashes(crashed);
+        }
+        self.take_off_ramps();
+        if !self.is_periodic() {
+            self.remove_exits();
+        }
+        self.admit();
+        self.use_ramps();
+        self.update_sections();
+        self.change_lanes();
+    }
+
+    pub fn run_until(&mut self, tmax: f64) {
+        // Half a step of slack keeps accumulated round-off from adding a step.
+        while self.time + 0.5 * self.dt < tmax && !self.aborted {
+            self.step();
+        }
+    }
+
+    pub fn run_observed(&mut self, tmax: f64, observer: &mut dyn Observer) {
+        observer.observe(self);
+        while self.time + 0.5 * self.dt < tmax && !self.aborted {
+            self.step();
+            observer.observe(self);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::CollisionPolicy;
+    use crate::integrator::IntegratorKind;
+    use crate::inflow::{Arrivals, SpeedDistribution};
+    use crate::lane::Mobil;
+    use crate::model::{Idm, Model};
+    use crate::{Actuated, Anticipation, CarId, Fixture, FixtureId, FixtureKind, Limits, Noise};
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_simulation_time() {
+        let items = vec![TrafficItem::Car(Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64))];
+        let mut sim = Simulation::new(Road::try_from(TrafficList::new(items)).unwrap(), 100f64, 1e-1);
+
+        sim.step();
+        assert_abs_diff_eq!(sim.time(), 0.1f64, epsilon = 1e-12);
+
+        // The simulation owns all of its state and may run on another thread
+        let sim = std::thread::spawn(move || {
+            sim.run_until(40f64);
+            sim
+        })
+        .join()
+        .unwrap();
+        assert_abs_diff_eq!(sim.time(), 40f64, epsilon = 1e-9);
+
+        let car = sim.cars().next().unwrap();
+        assert_abs_diff_eq!(car.vel[0], 10f64, epsilon = 1e-2);
+        assert!(car.pos[0] >= 0f64 && car.pos[0] < 100f64);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid time step")]
+    fn test_simulation_nan_step() {
+        Simulation::new(Road::from_cars(vec![], vec![]).unwrap(), 100f64, f64::NAN);
+    }
+
+    #[test]
+    fn test_simulation_ring() {
+        let speedcam = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 100f64, false);
+
+        let mut cars = vec![];
+        for k in 0..5 {
+            let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+            car.pos[0] = 20f64 * k as f64;
+            cars.push(car);
+        }
+
+        let mut sim = Simulation::new(Road::from_cars(cars, vec![speedcam]).unwrap(), 1000f64, 1e-1);
+        sim.run_until(300f64);
+
+        assert_eq!(sim.cars().count(), 5);
+        let pos: Vec<f64> = sim.traffic().iter().map(|item| item.pos()[0]).collect();
+        assert!(pos.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_simulation_ring_backward() {
+        // A car rolling back over the start of the ring drove no lap through the section behind it
+        let speedcam = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 100f64, true);
+        let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        car.pos[0] = 0.5;
+        car.vel[0] = -10f64;
+
+        let mut sim = Simulation::new(Road::from_cars(vec![car], vec![speedcam]).unwrap(), 1000f64, 1e-1);
+        sim.step();
+
+        let car = sim.cars().next().unwrap();
+        assert!(car.pos[0] > 999f64 && car.pos[0] < 1000f64);
+        assert_eq!(sim.traffic().position(CarId(0)), Some(2));
+        assert!(sim.traffic().sections().is_empty());
+        assert!(sim.violations().is_empty());
+    }
+
+    #[test]
+    fn test_simulation_limits() {
+        // Packed beyond the jam density of the IDM, cars would get unbounded forces
+        let limits = Limits { max_acceleration: 1f64, comfortable_deceleration: 2f64, emergency_deceleration: 6f64 };
+        let items = (0..40)
+            .map(|k| {
+                let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64).with_limits(limits);
+                car.pos[0] = 2.5 * k as f64;
+                car.vel[0] = 5f64;
+                TrafficItem::Car(car)
+            })
+            .collect();
+        let mut sim = Simulation::new(Road::try_from(TrafficList::new(items)).unwrap(), 100f64, 1e-1).with_model(Model::Idm(Idm::default()));
+        for _ in 0..500 {
+            sim.step();
+            assert!(sim.cars().all(|c| c.vel[0].is_finite() && c.vel[0] >= 0f64));
+        }
+
+        let brakings = sim.emergency_brakings();
+        assert!(!brakings.is_empty());
+        assert!(brakings.iter().all(|b| b.demanded < -2f64 && b.applied >= -6f64));
+
+        // Cars without limits of their own are unbounded, unless the simulation sets some for them
+        assert!(Simulation::new(Road::default(), 100f64, 1e-1).limits().is_none());
+        let items = (0..40)
+            .map(|k| {
+                let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+                car.pos[0] = 2.5 * k as f64;
+                car.vel[0] = 5f64;
+                TrafficItem::Car(car)
+            })
+            .collect();
+        let mut sim = Simulation::new(Road::try_from(TrafficList::new(items)).unwrap(), 100f64, 1e-1)
+            .with_model(Model::Idm(Idm::default()))
+            .with_limits(limits);
+        sim.run_until(50f64);
+        let brakings = sim.emergency_brakings();
+        assert!(!brakings.is_empty());
+        assert!(brakings.iter().all(|b| b.applied >= -6f64));
+        assert!(sim.cars().all(|c| c.vel[0].is_finite() && c.vel[0] >= 0f64));
+    }
+
+    #[test]
+    fn test_simulation_collisions() {
+        // With such a time step the moving car goes right through the standing one
+        let run = |policy: CollisionPolicy| {
+            let mut moving = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+            moving.vel[0] = 10f64;
+            let mut standing = Car::new(0, 1f64, 0f64, 1f64, 0f64, 0f64);
+            standing.pos[0] = 100f64;
+            let items = vec![TrafficItem::Car(moving), TrafficItem::Car(standing)];
+            let mut sim = Simulation::new(Road::try_from(TrafficList::new(items)).unwrap(), 1000f64, 15f64).with_collision_policy(policy);
+            sim.run_until(60f64);
+            sim
+        };
+
+        let sim = run(CollisionPolicy::Record);
+        assert_eq!(sim.crashes().len(), 1);
+        let crash = &sim.crashes()[0];
+        assert_eq!((crash.rear, crash.front), (CarId(0), CarId(1)));
+        assert!(crash.overlap > 1f64 && crash.is_near(100f64, 1f64));
+        assert_eq!(sim.cars().count(), 2);
+
+        assert_eq!(run(CollisionPolicy::Remove).cars().count(), 0);
+
+        let sim = run(CollisionPolicy::Block);
+        assert!(sim.cars().all(|c| c.is_blocked() && c.vel[0] == 0f64));
+        assert_eq!(sim.crashes().len(), 1);
+
+        let sim = run(CollisionPolicy::Abort);
+        assert!(sim.is_aborted());
+        assert_eq!(sim.time(), 15f64);
+    }
+
+    #[test]
+    fn test_simulation_integrators() {
+        // A perturbed ring of IDM cars, compared against a run with twenty times smaller steps
+        let run = |kind: IntegratorKind, dt: f64| {
+            let items = (0..20)
+                .map(|k| {
+                    let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+                    car.pos[0] = 10f64 * k as f64 + if k == 0 { 4f64 } else { 0f64 };
+                    car.vel[0] = 5f64;
+                    TrafficItem::Car(car)
+                })
+                .collect();
+            let mut sim = Simulation::new(Road::try_from(TrafficList::new(items)).unwrap(), 200f64, dt)
+                .with_model(Model::Idm(Idm::default()))
+                .with_integrator(kind);
+            let mut substeps = 0;
+            while sim.time() + 0.5 * dt < 30f64 {
+                sim.step();
+                substeps = substeps.max(sim.substeps());
+            }
+            let mut cars: Vec<(CarId, f64)> = sim.cars().map(|c| (c.listed_id(), c.pos[0])).collect();
+            cars.sort_by_key(|(id, _)| *id);
+            (cars, substeps)
+        };
+        let (reference, _) = run(IntegratorKind::Rk4, 5e-3);
+        let error = |kind: IntegratorKind, dt: f64| {
+            let (cars, substeps) = run(kind, dt);
+            let error = cars.iter().zip(reference.iter()).map(|((_, x), (_, r))| {
+                let d = (x - r).rem_euclid(200f64);
+                d.min(200f64 - d)
+            });
+            (error.fold(0f64, f64::max), substeps)
+        };
+        let (euler, _) = error(IntegratorKind::Euler, 0.1);
+        let (ballistic, _) = error(IntegratorKind::Ballistic, 0.1);
+        let (verlet, _) = error(IntegratorKind::Verlet, 0.1);
+        let (rk4, _) = error(IntegratorKind::Rk4, 0.1);
+        assert!(verlet < 0.1 * euler && verlet < 0.1 * ballistic);
+        assert!(rk4 < verlet);
+
+        // Larger steps are split up while the perturbation is strong
+        let (adaptive, substeps) = error(IntegratorKind::Adaptive, 0.5);
+        assert!(adaptive < euler);
+        assert!(substeps > 1);
+    }
+
+    #[test]
+    fn test_simulation_many_cars() {
+        // Twenty thousand cars on two lanes, each only coupled to its neighbours
+        let n = 20000;
+        let length = 200000f64;
+        let items = (0..n)
+            .map(|k| {
+                let mut car = Car::new(k % 2, 1f64, 10f64, 1f64, 0f64, 10f64);
+                car.pos[0] = length * (k / 2) as f64 / (n / 2) as f64;
+                TrafficItem::Car(car)
+            })
+            .collect();
+        let mut sim = Simulation::new(Road::try_from(TrafficList::new(items)).unwrap(), length, 1e-1).with_lanes(2);
+        sim.run_until(10f64);
+
+        assert_eq!(sim.cars().count(), n);
+        assert!(sim.cars().all(|c| c.vel[0].is_finite() && c.vel[0] > 0f64));
+        let pos: Vec<f64> = sim.traffic().iter().map(|item| item.pos()[0]).collect();
+        assert!(pos.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_simulation_seed() {
+        let run = |seed: u64| {
+            let noise = Noise::OrnsteinUhlenbeck { sigma: 0.5, tau: 1f64 };
+            let items = (0..10)
+                .map(|k| {
+                    let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64).with_noise(noise.clone());
+                    car.pos[0] = 50f64 * k as f64;
+                    TrafficItem::Car(car)
+                })
+                .collect();
+            let mut sim = Simulation::new(Road::try_from(TrafficList::new(items)).unwrap(), 500f64, 1e-1).with_seed(seed);
+            sim.run_until(50f64);
+            sim.cars().map(|c| c.pos[0]).collect::<Vec<f64>>()
+        };
+        assert_eq!(run(3), run(3));
+        assert_ne!(run(3), run(4));
+
+        // A run saved halfway and resumed, here or on another thread, goes on exactly like the original
+        let template = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64).with_noise(Noise::RandomBraking { rate: 0.05, deceleration: 2f64, duration: 2f64 });
+        let inflow = Inflow::new(Arrivals::Poisson { rate: 0.2 }, SpeedDistribution::Uniform { min: 5f64, max: 10f64 }, template);
+        let mut sim = Simulation::new(Road::from_cars(vec![], vec![]).unwrap(), 500f64, 1e-1)
+            .with_open_boundary()
+            .with_inflow(inflow)
+            .with_lanes(2)
+            .with_lane_change(Mobil::default())
+            .with_seed(7);
+        sim.run_until(50f64);
+        let json = serde_json::to_string(&sim).unwrap();
+        let mut copy = sim.clone();
+        let mut resumed: Simulation = std::thread::spawn(move || serde_json::from_str(&json).unwrap()).join().unwrap();
+        for s in [&mut sim, &mut copy, &mut resumed] {
+            s.run_until(150f64);
+        }
+        let state = |s: &Simulation| (s.exits().len(), s.cars().map(|c| (c.id(), c.pos[0], c.lane)).collect::<Vec<_>>());
+        assert!(!sim.exits().is_empty());
+        assert_eq!(state(&copy), state(&sim));
+        assert_eq!(state(&resumed), state(&sim));
+
+        // A checkpoint is read back through the same checks as the builders
+        let checkpoint = serde_json::to_value(&sim).unwrap();
+        assert!(serde_json::from_value::<Simulation>(checkpoint.clone()).is_ok());
+        for (key, value) in [("dt", serde_json::json!(0.0)), ("periodic", serde_json::json!(true)), ("lanes", serde_json::json!(0))] {
+            let mut tampered = checkpoint.clone();
+            tampered[key] = value;
+            assert!(serde_json::from_value::<Simulation>(tampered).is_err());
+        }
+    }
+
+    #[test]
+    fn test_simulation_open() {
+        // Cars enter every 4 time units at speed 10 and need 50 to cross a road of 500
+        let speedcam = SpeedCam::new(Cartessian1D::new([300f64]), 5f64, 100f64, false);
+        let template = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        let inflow = Inflow::new(Arrivals::FixedHeadway { headway: 4f64 }, SpeedDistribution::Fixed { speed: 10f64 }, template);
+        let mut sim = Simulation::new(Road::from_cars(vec![], vec![speedcam]).unwrap(), 500f64, 1e-1)
+            .with_open_boundary()
+            .with_inflow(inflow);
+        sim.run_until(198f64);
+
+        let exits = sim.exits();
+        assert!(exits.len() >= 30);
+        assert_eq!(exits.len() + sim.cars().count(), 49);
+        assert!(sim.cars().all(|c| c.pos[0] < 500f64));
+        // The camera zone slows every car down
+        assert!(exits.iter().all(|e| e.travel_time().unwrap() > 55f64));
+        assert!(exits.windows(2).all(|w| w[0].car < w[1].car));
+        assert_eq!(sim.inflows()[0].queue(), 0);
+    }
+
+    #[test]
+    fn test_simulation_section_control() {
+        let point = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 200f64, false);
+        let section = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 200f64, true);
+
+        let run = |cam: &SpeedCam, behavior: f64| {
+            let car = Car::new(0, 1f64, 10f64, 1f64, behavior, 10f64);
+            let mut sim = Simulation::new(Road::from_cars(vec![car], vec![cam.clone()]).unwrap(), 1000f64, 1e-1);
+            sim.run_until(100f64);
+            (sim.sections().to_vec(), sim.violations())
+        };
+
+        let (sections, tickets) = run(&point, -0.05);
+        assert!(sections.is_empty());
+        assert!(tickets.is_empty());
+
+        let (compliant, tickets) = run(&section, -0.05);
+        assert!(tickets.is_empty());
+        assert_eq!(compliant.len(), 1);
+        assert!(!compliant[0].is_violation());
+        assert_abs_diff_eq!(compliant[0].average_speed, 4.75, epsilon = 0.1);
+
+        // A driver above the limit but within the tolerance gets neither a ticket nor a violation
+        let (tolerated, tickets) = run(&section, 0.05);
+        assert!(tickets.is_empty());
+        assert!(!tolerated[0].is_violation());
+        assert!(tolerated[0].average_speed > 5f64);
+
+        // Overlapping sections each keep their own clock, and the driver heeds the stricter of them
+        let inner = SpeedCam::new(Cartessian1D::new([450f64]), 5f64, 100f64, true);
+        let car = Car::new(0, 1f64, 10f64, 1f64, -0.05, 10f64);
+        let mut sim = Simulation::new(Road::from_cars(vec![car], vec![section.clone(), inner]).unwrap(), 1000f64, 1e-1);
+        sim.run_until(100f64);
+        assert_eq!(sim.sections().len(), 2);
+        assert!(sim.sections().iter().all(|s| !s.is_violation() && s.elapsed > 0f64));
+        assert!(sim.violations().is_empty());
+
+        // Without point enforcement a speeding driver keeps its own pace through the section
+        let (speeding, tickets) = run(&section, 0.3);
+        assert_eq!(speeding.len(), 1);
+        assert!(speeding[0].is_violation());
+        assert!(speeding[0].average_speed > 6f64);
+        assert_eq!(tickets.len(), 1);
+        assert!(tickets.iter().all(|v| v.average && v.measured_speed == speeding[0].average_speed));
+    }
+
+    #[test]
+    fn test_simulation_anticipation() {
+        // Drivers who notice the camera are down to the limit when they reach its zone and speed up harder past it
+        let cam = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 100f64, false).with_sight_distance(150f64);
+
+        let run = |awareness: f64| {
+            let anticipation = Anticipation { awareness, ..Anticipation::default() };
+            let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64).with_anticipation(anticipation);
+            car.vel[0] = 10f64;
+            let mut sim = Simulation::new(Road::from_cars(vec![car], vec![cam.clone()]).unwrap(), 1000f64, 1e-1).with_seed(1);
+            // Speeds on reaching the zone and 50 past it
+            let mut speeds = vec![];
+            for x in [399f64, 550f64] {
+                while sim.cars().next().unwrap().pos[0] < x {
+                    sim.step();
+                }
+                speeds.push(sim.cars().next().unwrap().speed());
+            }
+            speeds
+        };
+
+        let aware = run(1f64);
+        let unaware = run(0f64);
+        assert!(aware[0] < 5.5);
+        assert!(unaware[0] > 9f64);
+        assert!(aware[1] > unaware[1]);
+    }
+
+    #[test]
+    fn test_simulation_fixtures() {
+        let car = |lane: usize, pos: f64| {
+            let mut car = Car::new(lane, 1f64, 10f64, 1f64, 0f64, 10f64);
+            car.pos[0] = pos;
+            car
+        };
+        let road = |cars: Vec<Car>, fixtures: Vec<Fixture>| Road::from_cars(cars, vec![]).unwrap().with_fixtures(fixtures).unwrap();
+
+        // Red over the first 30, the car waits at the light then drives through
+        let light = Fixture::new(300f64, FixtureKind::TrafficLight { cycle: 60f64, green: 30f64, offset: 30f64 });
+        let mut sim = Simulation::new(road(vec![car(0, 150f64)], vec![light]), 1000f64, 1e-1);
+        sim.run_until(29f64);
+        let waiting = sim.cars().next().unwrap();
+        assert!(waiting.pos[0] > 280f64 && waiting.pos[0] < 300f64);
+        sim.run_until(45f64);
+        assert!(sim.cars().next().unwrap().pos[0] > 300f64);
+
+        // The car comes to a halt at the stop sign, and past the speed sign keeps to its limit
+        let stop = Fixture::new(200f64, FixtureKind::StopSign);
+        let sign = Fixture::new(400f64, FixtureKind::SpeedLimit { limit: Some(5f64) });
+        let mut sim = Simulation::new(road(vec![car(0, 0f64)], vec![stop, sign]), 1000f64, 1e-1);
+        let mut slowest = f64::INFINITY;
+        while sim.cars().next().unwrap().pos[0] < 450f64 {
+            sim.step();
+            let c = sim.cars().next().unwrap();
+            if c.pos[0] > 150f64 && c.pos[0] < 200f64 {
+                slowest = slowest.min(c.speed());
+            }
+        }
+        assert!(slowest < 0.5);
+        assert_eq!(sim.cars().next().unwrap().max_speed(), 5f64);
+
+        // A higher sign inside a camera zone waits for the end of the zone
+        let speedcam = SpeedCam::new(Cartessian1D::new([500f64]), 3f64, 200f64, false);
+        let sign = Fixture::new(400f64, FixtureKind::SpeedLimit { limit: Some(8f64) });
+        let zone = Road::from_cars(vec![car(0, 0f64)], vec![speedcam]).unwrap().with_fixtures(vec![sign]).unwrap();
+        let mut sim = Simulation::new(zone, 1000f64, 1e-1);
+        while sim.cars().next().unwrap().pos[0] < 450f64 {
+            sim.step();
+        }
+        assert_eq!(sim.cars().next().unwrap().max_speed(), 3f64);
+        while sim.cars().next().unwrap().pos[0] < 550f64 {
+            sim.step();
+        }
+        assert_eq!(sim.cars().next().unwrap().max_speed(), 8f64);
+
+        // A car starting halfway through a section keeps to its limit without being timed over the whole of it
+        let section = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 200f64, true);
+        let sign = Fixture::new(900f64, FixtureKind::SpeedLimit { limit: Some(8f64) });
+        let zone = Road::from_cars(vec![car(0, 400f64)], vec![section]).unwrap().with_fixtures(vec![sign]).unwrap();
+        let mut sim = Simulation::new(zone, 1000f64, 1e-1);
+        assert_eq!(sim.cars().next().unwrap().max_speed(), 5f64);
+        sim.run_until(40f64);
+        assert!(sim.cars().next().unwrap().pos[0] > 550f64);
+        assert!(sim.sections().is_empty());
+
+        // The second lane ends, its car merges before the end
+        let drop = Fixture::new(300f64, FixtureKind::LaneDrop { lane: 1 });
+        let mut sim = Simulation::new(road(vec![car(0, 0f64), car(1, 100f64)], vec![drop]), 1000f64, 1e-1)
+            .with_lanes(2)
+            .with_lane_change(Mobil::default());
+        while sim.traffic().car(CarId(1)).unwrap().pos[0] < 290f64 {
+            sim.step();
+        }
+        assert!(sim.cars().all(|c| c.lane == 0));
+
+        // Without lane changes the car is held at the end of its lane until the car alongside has gone by
+        let drop = Fixture::new(300f64, FixtureKind::LaneDrop { lane: 1 });
+        let mut passing = car(0, 240f64);
+        passing.vel[0] = 10f64;
+        let mut sim = Simulation::new(road(vec![passing, car(1, 250f64)], vec![drop]), 1000f64, 1e-1).with_lanes(2);
+        sim.step();
+        assert_eq!(sim.traffic().car(CarId(1)).unwrap().lane, 1);
+        sim.run_until(60f64);
+        assert!(sim.crashes().is_empty());
+        assert!(sim.cars().all(|c| c.lane == 0));
+        assert!(sim.traffic().car(CarId(1)).unwrap().pos[0] > 300f64);
+
+        // Cars join at the on ramp and all leave at the off ramp
+        let ramps = vec![Fixture::new(100f64, FixtureKind::OnRamp { merge: 0f64 }), Fixture::new(600f64, FixtureKind::OffRamp { share: 1f64, diverge: 0f64 })];
+        let inflow = Inflow::new(Arrivals::FixedHeadway { headway: 5f64 }, SpeedDistribution::Fixed { speed: 10f64 }, car(0, 0f64))
+            .with_ramp(FixtureId(0));
+        let mut sim = Simulation::new(road(vec![], ramps), 1000f64, 1e-1).with_inflow(inflow);
+        sim.run_until(200f64);
+        assert_eq!(sim.exits().len() + sim.cars().count(), 39);
+        assert!(sim.exits().len() > 25);
+        assert!(sim.exits().iter().all(|e| e.travel_time().unwrap() > 45f64));
+        assert!(sim.cars().all(|c| c.pos[0] >= 100f64 && c.pos[0] < 600f64));
+    }
+
+    #[test]
+    fn test_simulation_ramps() {
+        // Ramp cars merge within the acceleration lane, cars bound for the off ramp move over to the first lane to take it
+        let ramps = vec![
+            Fixture::new(200f64, FixtureKind::OnRamp { merge: 150f64 }),
+            Fixture::new(700f64, FixtureKind::OffRamp { share: 0f64, diverge: 400f64 }),
+        ];
+        let car = |lane: usize| Car::new(lane, 1f64, 10f64, 1f64, 0f64, 10f64);
+        let inflow = |headway: f64, template: Car| Inflow::new(Arrivals::FixedHeadway { headway }, SpeedDistribution::Fixed { speed: 10f64 }, template);
+        let mut sim = Simulation::new(Road::from_cars(vec![], vec![]).unwrap().with_fixtures(ramps).unwrap(), 1000f64, 1e-1)
+            .with_lanes(2)
+            .with_open_boundary()
+            .with_inflow(inflow(12f64, car(0)))
+            .with_inflow(inflow(6f64, car(1).with_destination(FixtureId(1))))
+            .with_inflow(inflow(20f64, car(0)).with_ramp(FixtureId(0)))
+            .with_model(Model::Idm(Idm::default()))
+            .with_integrator(IntegratorKind::Ballistic);
+
+        while sim.time() < 300f64 {
+            sim.step();
+            assert!(sim.cars().filter(|c| c.merging().is_some()).all(|c| c.pos[0] >= 200f64 && c.pos[0] <= 350f64));
+        }
+        assert!(sim.crashes().is_empty());
+        let taken: Vec<&Exit> = sim.exits().iter().filter(|e| e.ramp == Some(FixtureId(1))).collect();
+        assert!(taken.len() > 25);
+        assert!(taken.iter().all(|e| e.lane == 0));
+        assert!(sim.missed_exits() * 2 < taken.len());
+        assert!(sim.cars().all(|c| c.pos[0] <= 700f64 || c.destination().is_none()));
+        assert!(sim.exits().iter().filter(|e| e.ramp.is_none()).count() > 25);
+
+        // Without room to diverge a car on the second lane misses its exit, which is counted once
+        let ramps = vec![Fixture::new(100f64, FixtureKind::OffRamp { share: 0f64, diverge: 0f64 })];
+        let mut late = car(1).with_destination(FixtureId(0));
+        late.pos[0] = 50f64;
+        late.vel[0] = 10f64;
+        let mut sim = Simulation::new(Road::from_cars(vec![late], vec![]).unwrap().with_fixtures(ramps).unwrap(), 1000f64, 1e-1).with_lanes(2);
+        sim.run_until(200f64);
+        assert_eq!(sim.missed_exits(), 1);
+        assert!(sim.exits().is_empty());
+        assert_eq!(sim.cars().next().unwrap().destination(), None);
+
+        // Cars without a destination only take the ramp from the first lane
+        let ramps = vec![Fixture::new(100f64, FixtureKind::OffRamp { share: 1f64, diverge: 0f64 })];
+        let (mut near, mut outer) = (car(0), car(1));
+        near.pos[0] = 50f64;
+        outer.pos[0] = 60f64;
+        let mut sim = Simulation::new(Road::from_cars(vec![near, outer], vec![]).unwrap().with_fixtures(ramps).unwrap(), 1000f64, 1e-1).with_lanes(2);
+        sim.run_until(200f64);
+        assert_eq!(sim.exits().len(), 1);
+        assert_eq!(sim.exits()[0].lane, 0);
+        assert_eq!(sim.cars().map(|c| c.lane).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn test_simulation_signals() {
+        // A steady stream keeps calling the actuated light, which queues it up in the camera zone once it maxes out
+        let plan = Actuated { min_green: 10f64, max_green: 40f64, gap: 3f64, red: 30f64, detector: 50f64 };
+        let cam = SpeedCam::new(Cartessian1D::new([495f64]), 15f64, 100f64, false);
+        let road = Road::from_cars(vec![], vec![cam]).unwrap().with_fixtures(vec![Fixture::new(500f64, FixtureKind::ActuatedLight(plan))]).unwrap();
+        let template = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        let inflow = Inflow::new(Arrivals::FixedHeadway { headway: 2f64 }, SpeedDistribution::Fixed { speed: 10f64 }, template);
+        let mut sim = Simulation::new(road, 1000f64, 1e-1)
+            .with_open_boundary()
+            .with_inflow(inflow)
+            .with_model(Model::Idm(Idm::default()))
+            .with_integrator(IntegratorKind::Ballistic);
+        let signal = |sim: &Simulation| *sim.road().fixture(FixtureId(0)).signal().unwrap();
+
+        // Nobody reaches the detector before the first green gaps out
+        sim.run_until(20f64);
+        assert!(!signal(&sim).is_green());
+        assert_abs_diff_eq!(signal(&sim).since(), 10f64, epsilon = 0.2);
+        sim.run_until(75f64);
+        assert!(signal(&sim).is_green());
+        assert_abs_diff_eq!(signal(&sim).since(), 40f64, epsilon = 0.2);
+
+        sim.run_until(100f64);
+        assert!(!signal(&sim).is_green());
+        assert_abs_diff_eq!(signal(&sim).since(), 80f64, epsilon = 0.2);
+        let queued: Vec<CarId> = sim.cars().filter(|c| c.speed() < 0.5 && c.pos[0] > 395f64 && c.pos[0] < 495f64).map(|c| c.listed_id()).collect();
+        assert!(queued.len() > 2);
+        sim.run_until(115f64);
+        assert!(signal(&sim).is_green());
+        sim.run_until(130f64);
+        assert!(queued.iter().all(|&id| sim.traffic().car(id).is_none_or(|c| c.pos[0] > 500f64)));
+        assert!(sim.violations().is_empty());
+    }
+
+    #[test]
+    fn test_simulation_detector_calls() {
+        // A car standing between the detector and the stop line does not call the light, one going over the detector does
+        let plan = Actuated { min_green: 10f64, max_green: 40f64, gap: 3f64, red: 30f64, detector: 50f64 };
+        let light = || Fixture::new(500f64, FixtureKind::ActuatedLight(plan));
+        let mut parked = Car::new(1, 1f64, 0f64, 0f64, 0f64, 0f64);
+        parked.pos[0] = 480f64;
+        let mut passing = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        passing.pos[0] = 360f64;
+        passing.vel[0] = 10f64;
+        let signal = |sim: &Simulation| *sim.road().fixture(FixtureId(0)).signal().unwrap();
+
+        let mut sim = Simulation::new(Road::from_cars(vec![parked.clone()], vec![]).unwrap().with_fixtures(vec![light()]).unwrap(), 1000f64, 1e-1);
+        sim.run_until(15f64);
+        assert!(!signal(&sim).is_green());
+        assert_abs_diff_eq!(signal(&sim).since(), 10f64, epsilon = 0.2);
+
+        let road = Road::from_cars(vec![parked, passing], vec![]).unwrap().with_fixtures(vec![light()]).unwrap();
+        let mut sim = Simulation::new(road, 1000f64, 1e-1).with_lanes(2);
+        sim.run_until(15f64);
+        assert!(!signal(&sim).is_green());
+        assert_abs_diff_eq!(signal(&sim).since(), 12f64, epsilon = 0.2);
+    }
+
+    #[test]
+    fn test_simulation_violations() {
+        // A short zone cannot slow a fast car down before the camera
+        let speedcam = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 0.05, false);
+        let lenient = SpeedCam::new(Cartessian1D::new([800f64]), 5f64, 0.05, false).with_tolerance(2.5);
+        let car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        let mut sim = Simulation::new(Road::from_cars(vec![car], vec![speedcam, lenient]).unwrap(), 1000f64, 1e-1);
+        sim.run_until(250f64);
+
+        let log = sim.violations();
+        assert_eq!(log.len(), 2);
+        assert!(log.iter().all(|v| v.pos == 500f64 && !v.average && v.measured_speed > 5.5));
+        assert_eq!(log.of_car(CarId(0)).count(), 2);
+        assert!(sim.cams()[1].violations().is_empty());
+
+        let first = log.iter().next().unwrap();
+        assert!(first.time > 50f64 && first.time < sim.time());
+    }
+
+    #[test]
+    fn test_simulation_lanes() {
+        let mut slow = Car::new(0, 1f64, 2f64, 1f64, 0f64, 2f64);
+        slow.pos[0] = 30f64;
+        let mut other = Car::new(1, 1f64, 2f64, 1f64, 0f64, 2f64);
+        other.pos[0] = 10.5;
+        let fast = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        let items = vec![
+            TrafficItem::Car(fast),
+            TrafficItem::Car(other),
+            TrafficItem::Car(slow),
+        ];
+
+        // Without lane changes the fast car stays behind the slow one
+        let mut sim = Simulation::new(Road::try_from(TrafficList::new(items.clone())).unwrap(), 1000f64, 1e-1).with_lanes(2);
+        sim.run_until(30f64);
+        let last = sim.cars().last().unwrap();
+        assert_eq!(last.own_max_speed, 2f64);
+
+        let mut sim = Simulation::new(Road::try_from(TrafficList::new(items)).unwrap(), 1000f64, 1e-1)
+            .with_lanes(2)
+            .with_lane_change(Mobil::default());
+        sim.run_until(30f64);
+        let last = sim.cars().last().unwrap();
+        assert_eq!(last.own_max_speed, 10f64);
+
+        let pos: Vec<f64> = sim.traffic().iter().map(|item| item.pos()[0]).collect();
+        assert!(pos.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid model")]
+    fn test_simulation_model_invalid() {
+        let car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64);
+        let idm = Idm { min_gap: 0f64, ..Idm::default() };
+        let sim = Simulation::new(Road::from_cars(vec![car], vec![]).unwrap(), 1000f64, 1e-1);
+        assert!(sim.clone().try_with_model(Model::Idm(idm.clone())).is_err());
+        sim.with_model(Model::Idm(idm));
+    }
+
+    #[test]
+    #[should_panic(expected = "car on lane 1 of a road with 1 lanes")]
+    fn test_simulation_lanes_invalid() {
+        // The index slot past the last lane belongs to merging cars, no car may be placed there
+        let car = Car::new(1, 1f64, 10f64, 1f64, 0f64, 10f64);
+        let sim = Simulation::new(Road::from_cars(vec![car], vec![]).unwrap(), 1000f64, 1e-1);
+        assert_eq!(sim.lanes(), 2);
+        sim.with_lanes(1);
+    }
+}
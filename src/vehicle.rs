@@ -0,0 +1,140 @@
+use crate::{Car, Limits};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VehicleClass {
+    #[default]
+    Car,
+    Truck,
+    Bus,
+    Motorcycle,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClassParams {
+    pub size: f64,
+    // Relative to the mass of a car
+    pub mass: f64,
+    pub limits: Limits,
+    pub desired_speed: f64,
+    // Highest lane the class may use, lanes being counted from the right
+    pub max_lane: Option<usize>,
+}
+
+impl VehicleClass {
+    pub fn preset(self) -> ClassParams {
+        // Lengths in m, accelerations in m/s^2 and speeds in m/s
+        match self {
+            VehicleClass::Car => ClassParams {
+                size: 4.5,
+                mass: 1.0,
+                limits: Limits {
+                    max_acceleration: 2.5,
+                    comfortable_deceleration: 3.0,
+                    emergency_deceleration: 8.0,
+                },
+                desired_speed: 33.3,
+                max_lane: None,
+            },
+            VehicleClass::Truck => ClassParams {
+                size: 12.0,
+                mass: 10.0,
+                limits: Limits {
+                    max_acceleration: 1.0,
+                    comfortable_deceleration: 2.0,
+                    emergency_deceleration: 5.0,
+                },
+                desired_speed: 25.0,
+                max_lane: Some(1),
+            },
+            VehicleClass::Bus => ClassParams {
+                size: 12.0,
+                mass: 8.0,
+                limits: Limits {
+                    max_acceleration: 1.2,
+                    comfortable_deceleration: 1.5,
+                    emergency_deceleration: 5.0,
+                },
+                desired_speed: 27.8,
+                max_lane: Some(1),
+            },
+            VehicleClass::Motorcycle => ClassParams {
+                size: 2.2,
+                mass: 0.2,
+                limits: Limits {
+                    max_acceleration: 4.0,
+                    comfortable_deceleration: 3.5,
+                    emergency_deceleration: 9.0,
+                },
+                desired_speed: 36.1,
+                max_lane: None,
+            },
+        }
+    }
+
+    pub fn allows_lane(self, lane: usize) -> bool {
+        !matches!(self.preset().max_lane, Some(max) if lane > max)
+    }
+
+    pub fn build(self, lane: usize, behavior: f64) -> Car {
+        // The drift gives the preset acceleration from rest, `Car::with_class` scales it by the mass
+        let p = self.preset();
+        Car::new(lane, p.size, p.desired_speed, p.limits.max_acceleration, behavior, p.desired_speed).with_class(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{CarFollowingModel, LennardJones};
+    use crate::{Road, Simulation, SpeedCam, TrafficItem, TrafficList};
+    use moldybrody::prelude::*;
+
+    #[test]
+    fn test_presets() {
+        let car = VehicleClass::Car.build(0, 0f64);
+        let truck = VehicleClass::Truck.build(0, 0f64);
+        assert_eq!(truck.class(), VehicleClass::Truck);
+        assert!(truck.size > car.size && truck.mass > car.mass);
+        assert!(LennardJones.free_acceleration(&truck) < LennardJones.free_acceleration(&car));
+        assert!(truck.allows_lane(1) && !truck.allows_lane(2));
+        assert!(car.allows_lane(5));
+        assert_eq!(truck.limits().unwrap().max_acceleration, 1f64);
+    }
+
+    #[test]
+    fn test_class_limit() {
+        // The same speed is fine for a car and a ticket for a truck
+        let mut cam = SpeedCam::new(Cartessian1D::new([100f64]), 5f64, 50f64, false).with_class_limit(VehicleClass::Truck, 4f64);
+        let items = vec![VehicleClass::Car, VehicleClass::Truck].into_iter().map(|c| TrafficItem::Car(c.build(0, 0f64))).collect();
+        let traffic = TrafficList::new(items);
+        let cars: Vec<&Car> = traffic.cars().collect();
+        assert!(!cam.measure(cars[0], 5.2, 1f64, false));
+        assert!(cam.measure(cars[1], 5.2, 1f64, false));
+        assert_eq!(cam.violations().iter().next().unwrap().speed_limit, 4f64);
+
+        let mut truck = cars[1].clone();
+        cam.set_max_speed(&mut truck);
+        assert_eq!(truck.max_speed(), 4f64);
+    }
+
+    #[test]
+    fn test_class_camera_braking() {
+        // Camera braking and drift are accelerations, so a heavy truck slows down in the zone as a car does
+        let cam = SpeedCam::new(Cartessian1D::new([500f64]), 5f64, 100f64, false);
+        let speed_at_camera = |class: VehicleClass| {
+            let mut car = Car::new(0, 1f64, 10f64, 1f64, 0f64, 10f64).with_class(class);
+            car.pos[0] = 380f64;
+            car.vel[0] = 10f64;
+            let mut sim = Simulation::new(Road::from_cars(vec![car], vec![cam.clone()]).unwrap(), 1000f64, 1e-1);
+            while sim.cars().next().unwrap().pos[0] < 495f64 {
+                sim.step();
+            }
+            sim.cars().next().unwrap().vel[0]
+        };
+        let (car, truck) = (speed_at_camera(VehicleClass::Car), speed_at_camera(VehicleClass::Truck));
+        assert!(car < 7f64);
+        assert!((truck - car).abs() < 0.1);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::CarId;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Violation {
+    pub car: CarId,
+    pub time: f64,
+    pub pos: f64,
+    pub measured_speed: f64,
+    pub speed_limit: f64,
+    pub tolerance: f64,
+    pub average: bool,
+}
+
+impl Violation {
+    pub fn excess(&self) -> f64 {
+        self.measured_speed - self.speed_limit
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ViolationLog {
+    events: Vec<Violation>,
+}
+
+impl ViolationLog {
+    pub fn new() -> Self {
+        Self { events: vec![] }
+    }
+
+    pub fn push(&mut self, violation: Violation) {
+        self.events.push(violation);
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Violation> {
+        self.events.iter()
+    }
+
+    pub fn of_car(&self, car: CarId) -> impl Iterator<Item = &Violation> {
+        self.events.iter().filter(move |v| v.car == car)
+    }
+
+    pub fn between(&self, t0: f64, t1: f64) -> impl Iterator<Item = &Violation> {
+        self.events.iter().filter(move |v| t0 <= v.time && v.time < t1)
+    }
+
+    pub fn extend(&mut self, other: &ViolationLog) {
+        self.events.extend(other.events.iter().cloned());
+    }
+
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writeln!(writer, "car,time,pos,measured_speed,speed_limit,tolerance,average")?;
+        for v in self.events.iter() {
+            writeln!(writer, "{},{},{},{},{},{},{}", v.car, v.time, v.pos, v.measured_speed, v.speed_limit, v.tolerance, v.average)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_violation_log() {
+        let mut log = ViolationLog::new();
+        for k in 0..4 {
+            log.push(Violation {
+                car: CarId(k % 2),
+                time: 10f64 * k as f64,
+                pos: 500f64,
+                measured_speed: 6f64 + k as f64,
+                speed_limit: 5f64,
+                tolerance: 1.1,
+                average: false,
+            });
+        }
+
+        assert_eq!(log.len(), 4);
+        assert_eq!(log.between(5f64, 25f64).count(), 2);
+        assert_eq!(log.of_car(CarId(1)).count(), 2);
+        assert_eq!(log.iter().map(|v| v.excess()).sum::<f64>(), 10f64);
+
+        let mut csv = vec![];
+        log.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 5);
+        assert_eq!(csv.lines().nth(1).unwrap(), "0,0,500,6,5,1.1,false");
+    }
+}